@@ -0,0 +1,8 @@
+CREATE TABLE deploys (
+	deploy_id			INTEGER NOT NULL PRIMARY KEY,
+	commit_id			VARCHAR(40) NOT NULL,
+	message				TEXT,
+	author				TEXT,
+	trigger				TEXT NOT NULL,
+	created_at			UNSIGNED INTEGER NOT NULL
+);
@@ -0,0 +1,15 @@
+CREATE TABLE deploys_old (
+	deploy_id			INTEGER NOT NULL PRIMARY KEY,
+	commit_id			VARCHAR(40) NOT NULL,
+	message				TEXT,
+	author				TEXT,
+	trigger				TEXT NOT NULL,
+	created_at			UNSIGNED INTEGER NOT NULL
+);
+
+INSERT INTO deploys_old (deploy_id, commit_id, message, author, trigger, created_at)
+	SELECT deploy_id, commit_id, message, author, trigger, created_at FROM deploys
+	WHERE succeeded = 1;
+
+DROP TABLE deploys;
+ALTER TABLE deploys_old RENAME TO deploys;
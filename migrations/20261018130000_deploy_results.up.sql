@@ -0,0 +1,21 @@
+-- failed deploys are recorded too so the commit becomes optional, sqlite
+-- can't relax a NOT NULL in place so the table is rebuilt
+
+CREATE TABLE deploys_new (
+	deploy_id			INTEGER NOT NULL PRIMARY KEY,
+	commit_id			VARCHAR(40),
+	message				TEXT,
+	author				TEXT,
+	trigger				TEXT NOT NULL,
+	created_at			UNSIGNED INTEGER NOT NULL,
+	duration_ms			UNSIGNED INTEGER NOT NULL DEFAULT 0,
+	objects_received	UNSIGNED INTEGER,
+	succeeded			BOOLEAN NOT NULL DEFAULT 1,
+	error				TEXT
+);
+
+INSERT INTO deploys_new (deploy_id, commit_id, message, author, trigger, created_at)
+	SELECT deploy_id, commit_id, message, author, trigger, created_at FROM deploys;
+
+DROP TABLE deploys;
+ALTER TABLE deploys_new RENAME TO deploys;
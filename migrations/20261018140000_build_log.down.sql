@@ -0,0 +1 @@
+ALTER TABLE deploys DROP COLUMN build_log;
@@ -0,0 +1 @@
+ALTER TABLE deploys ADD COLUMN build_log TEXT;
@@ -0,0 +1,33 @@
+-- only the default site's analytics fit the old schema
+CREATE TABLE paths_old (
+	path_id 			INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+	path				TEXT NOT NULL UNIQUE,
+	unique_visitors		INTEGER NOT NULL DEFAULT 0,
+	total_requests		INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT INTO paths_old (path_id, path, unique_visitors, total_requests)
+	SELECT path_id, path, unique_visitors, total_requests FROM paths
+	WHERE site = 'default';
+
+CREATE TABLE requests_old (
+	id 					INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+	visitor_id			INTEGER NOT NULL,
+	path_id				INTEGER NOT NULL,
+	user_agent			TEXT NOT NULL,
+	method				UNSIGNED TINYINT NOT NULL,
+	status				UNSIGNED SMALLINT NOT NULL,
+	created_at			UNSIGNED INTEGER NOT NULL,
+	FOREIGN KEY(path_id) 	REFERENCES paths_old(path_id),
+	FOREIGN KEY(visitor_id) 	REFERENCES visitors(visitor_id)
+);
+
+INSERT INTO requests_old SELECT * FROM requests
+	WHERE path_id IN (SELECT path_id FROM paths_old);
+
+DROP TABLE requests;
+DROP TABLE paths;
+ALTER TABLE paths_old RENAME TO paths;
+ALTER TABLE requests_old RENAME TO requests;
+
+ALTER TABLE deploys DROP COLUMN site;
@@ -0,0 +1,36 @@
+-- paths are now unique per site rather than globally. sqlite can't change a
+-- UNIQUE constraint in place so the table is rebuilt, and requests with it
+-- so their foreign key follows the new table rather than the dropped one
+CREATE TABLE paths_new (
+	path_id 			INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+	site				TEXT NOT NULL DEFAULT 'default',
+	path				TEXT NOT NULL,
+	unique_visitors		INTEGER NOT NULL DEFAULT 0,
+	total_requests		INTEGER NOT NULL DEFAULT 0,
+	UNIQUE(site, path)
+);
+
+INSERT INTO paths_new (path_id, path, unique_visitors, total_requests)
+	SELECT path_id, path, unique_visitors, total_requests FROM paths;
+
+CREATE TABLE requests_new (
+	id 					INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+	visitor_id			INTEGER NOT NULL,
+	path_id				INTEGER NOT NULL,
+	user_agent			TEXT NOT NULL,
+	method				UNSIGNED TINYINT NOT NULL,
+	status				UNSIGNED SMALLINT NOT NULL,
+	created_at			UNSIGNED INTEGER NOT NULL,
+	FOREIGN KEY(path_id) 	REFERENCES paths_new(path_id),
+	FOREIGN KEY(visitor_id) 	REFERENCES visitors(visitor_id)
+);
+
+INSERT INTO requests_new SELECT * FROM requests;
+
+DROP TABLE requests;
+DROP TABLE paths;
+-- renaming also points requests' foreign key back at paths
+ALTER TABLE paths_new RENAME TO paths;
+ALTER TABLE requests_new RENAME TO requests;
+
+ALTER TABLE deploys ADD COLUMN site TEXT NOT NULL DEFAULT 'default';
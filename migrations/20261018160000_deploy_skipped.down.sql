@@ -0,0 +1 @@
+ALTER TABLE deploys DROP COLUMN skipped;
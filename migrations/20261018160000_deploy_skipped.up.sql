@@ -0,0 +1,2 @@
+-- deploys that were queued but left alone because a rollback pinned the site
+ALTER TABLE deploys ADD COLUMN skipped BOOLEAN NOT NULL DEFAULT 0;
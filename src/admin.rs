@@ -0,0 +1,217 @@
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::Request;
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::{context, Template};
+
+use crate::analytics::Db;
+use crate::release;
+use crate::sites::{Site, Sites};
+use crate::webhook::constant_time_eq;
+
+/// a request carrying `Authorization: Bearer <admin_token>`, or basic auth
+/// with the token as the password so the dashboard works in a browser.
+/// admin routes are all refused when the site has no token configured
+pub struct Admin;
+
+/// pulls the token out of either a bearer or a basic authorization header
+fn given_token(header: &str) -> Option<String> {
+    use base64::Engine;
+
+    if let Some(token) = header.strip_prefix("Bearer ") {
+        return Some(token.to_string());
+    }
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (_user, password) = decoded.split_once(':')?;
+    Some(password.to_string())
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = match req
+            .rocket()
+            .state::<Sites>()
+            .and_then(|x| x.for_request(req))
+            .and_then(|x| x.config.admin_token.as_deref())
+        {
+            Some(x) => x,
+            None => return Outcome::Error((Status::Unauthorized, ())),
+        };
+        let given = req.headers().get_one("Authorization").and_then(given_token);
+
+        match given {
+            Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => {
+                Outcome::Success(Admin)
+            }
+            _ => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct RolledBack {
+    commit: String,
+    message: Option<String>,
+}
+
+#[post("/rollback/<commit>")]
+async fn rollback(
+    _admin: Admin,
+    mut db: Connection<Db>,
+    site: &Site,
+    commit: &str,
+) -> Result<Json<RolledBack>, Status> {
+    // only commits that have been live before can be rolled back to
+    let known = sqlx::query!(
+        "SELECT commit_id FROM deploys
+        WHERE site = $1 AND commit_id = $2 AND succeeded = 1 LIMIT 1",
+        site.config.name,
+        commit
+    )
+    .fetch_optional(&mut **db)
+    .await;
+
+    match known {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(Status::NotFound),
+        Err(x) => {
+            error!("failed to read deploy history: {}", x);
+            return Err(Status::InternalServerError);
+        }
+    }
+
+    match site.deployer.rollback(commit.to_string()).await {
+        Ok(deployed) => Ok(Json(RolledBack {
+            commit: deployed.commit.to_string(),
+            message: deployed.message,
+        })),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct DeployRecord {
+    deploy_id: i64,
+    commit_id: Option<String>,
+    summary: Option<String>,
+    author: Option<String>,
+    trigger: String,
+    created_at: i64,
+    deployed_at: String,
+    duration_ms: i64,
+    objects_received: Option<i64>,
+    succeeded: bool,
+    /// left alone because a rollback had pinned the site
+    skipped: bool,
+    error: Option<String>,
+    build_log: Option<String>,
+    /// whether this is the deploy currently being served
+    live: bool,
+}
+
+async fn deploy_history(db: &mut Connection<Db>, site: &Site) -> Result<Vec<DeployRecord>, Status> {
+    let limit = 100;
+    let rows = sqlx::query!(
+        "SELECT * FROM deploys WHERE site = $1 ORDER BY deploy_id DESC LIMIT $2",
+        site.config.name,
+        limit
+    )
+    .fetch_all(&mut ***db)
+    .await;
+
+    let rows = match rows {
+        Ok(x) => x,
+        Err(x) => {
+            error!("failed to read deploy history: {}", x);
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    // several deploys can share a commit, the newest successful one is live
+    let mut live_commit = release::current_commit(&site.config.live_dir);
+    let history = rows
+        .into_iter()
+        .map(|row| {
+            let live = row.succeeded && row.commit_id.is_some() && row.commit_id == live_commit;
+            if live {
+                live_commit = None;
+            }
+            DeployRecord {
+                deploy_id: row.deploy_id,
+                summary: row
+                    .message
+                    .as_deref()
+                    .and_then(|x| x.lines().next())
+                    .map(|x| x.to_string()),
+                commit_id: row.commit_id,
+                author: row.author,
+                trigger: row.trigger,
+                created_at: row.created_at,
+                deployed_at: chrono::DateTime::from_timestamp_millis(row.created_at)
+                    .map(|x| x.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                    .unwrap_or_default(),
+                duration_ms: row.duration_ms,
+                objects_received: row.objects_received,
+                succeeded: row.succeeded,
+                skipped: row.skipped,
+                error: row.error,
+                build_log: row.build_log,
+                live,
+            }
+        })
+        .collect();
+    Ok(history)
+}
+
+#[get("/deploys")]
+async fn deploys_json(
+    _admin: Admin,
+    mut db: Connection<Db>,
+    site: &Site,
+) -> Result<Json<Vec<DeployRecord>>, Status> {
+    Ok(Json(deploy_history(&mut db, site).await?))
+}
+
+#[get("/")]
+async fn dashboard(_admin: Admin, mut db: Connection<Db>, site: &Site) -> Result<Template, Status> {
+    let deploys = deploy_history(&mut db, site).await?;
+    Ok(Template::render(
+        "admin/deploys",
+        context! { deploys: deploys, site: &site.config.name, branch: &site.config.branch },
+    ))
+}
+
+/// asks the browser for credentials so the dashboard can be opened directly
+#[derive(Responder)]
+#[response(status = 401)]
+struct Unauthorized {
+    body: &'static str,
+    challenge: Header<'static>,
+}
+
+#[catch(401)]
+fn unauthorized() -> Unauthorized {
+    Unauthorized {
+        body: "unauthorized",
+        challenge: Header::new("WWW-Authenticate", "Basic realm=\"bloghoster admin\""),
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![dashboard, deploys_json, rollback]
+}
+
+pub fn catchers() -> Vec<rocket::Catcher> {
+    catchers![unauthorized]
+}
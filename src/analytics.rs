@@ -14,6 +14,9 @@ use rocket::response::Redirect;
 use rocket_db_pools::{Connection, Database, Initializer};
 use sqlx::{Acquire, Error};
 
+use crate::files;
+use crate::sites::{Site, Sites};
+
 #[derive(Database)]
 #[database("sqlx")]
 pub struct Db(sqlx::SqlitePool);
@@ -74,6 +77,7 @@ impl Method {
 
 #[derive(Debug, Clone)]
 struct RequestData {
+    site: String,
     ip_address: String,
     path: String,
     user_agent: String,
@@ -83,6 +87,7 @@ struct RequestData {
 
 impl RequestData {
     pub fn new(
+        site: String,
         ip_address: String,
         path: String,
         user_agent: String,
@@ -90,6 +95,7 @@ impl RequestData {
         status: u16,
     ) -> Self {
         Self {
+            site,
             ip_address,
             path,
             user_agent,
@@ -187,8 +193,8 @@ async fn log_request(request_data: RequestData, conn: Connection<Db>) {
     let mut transaction = val.begin().await.unwrap();
 
     let path_id = sqlx::query!(
-        "Select path_id FROM paths WHERE path = $1",
-        request_data.path
+        "Select path_id FROM paths WHERE site = $1 AND path = $2",
+        request_data.site, request_data.path
     )
     .fetch_optional(&mut *transaction)
     .await;
@@ -200,8 +206,8 @@ async fn log_request(request_data: RequestData, conn: Connection<Db>) {
         None => {
             let result =
             sqlx::query!(
-                "INSERT INTO paths (path, unique_visitors, total_requests) VALUES($1, $2, $3) RETURNING path_id",
-                request_data.path, 0, 0
+                "INSERT INTO paths (site, path, unique_visitors, total_requests) VALUES($1, $2, $3, $4) RETURNING path_id",
+                request_data.site, request_data.path, 0, 0
             )
                 .fetch_one(&mut *transaction)
                 .await;
@@ -210,8 +216,8 @@ async fn log_request(request_data: RequestData, conn: Connection<Db>) {
     };
 
     let _result = sqlx::query!(
-        "UPDATE paths SET total_requests = total_requests + 1 WHERE path = $1",
-        request_data.path
+        "UPDATE paths SET total_requests = total_requests + 1 WHERE path_id = $1",
+        path_id
     )
     .execute(&mut *transaction)
     .await;
@@ -246,8 +252,8 @@ async fn log_request(request_data: RequestData, conn: Connection<Db>) {
 
     if unique {
         let _result = sqlx::query!(
-            "UPDATE paths SET unique_visitors = unique_visitors + 1 WHERE path = $1",
-            request_data.path
+            "UPDATE paths SET unique_visitors = unique_visitors + 1 WHERE path_id = $1",
+            path_id
         )
         .execute(&mut *transaction)
         .await;
@@ -284,15 +290,26 @@ impl Fairing for Analytics {
             .get_one("User-Agent")
             .unwrap_or_default()
             .to_owned();
+        let site = req
+            .rocket()
+            .state::<Sites>()
+            .and_then(|x| x.for_request(req));
+
+        // /posts/hello, /posts/hello/ and /posts/hello.html are one page
         let path = self
             .mappers
             .path
             .as_ref()
             .map(|m| m(req, res))
-            .unwrap_or_else(|| req.uri().path().to_string());
+            .unwrap_or_else(|| match site {
+                Some(site) => files::page_key(req.uri().path().as_str(), &site.config.urls),
+                None => req.uri().path().to_string(),
+            });
+
+        let site = site.map(|x| x.config.name.clone()).unwrap_or_default();
 
         let request_data =
-            RequestData::new(ip_address, path, user_agent, method, res.status().code);
+            RequestData::new(site, ip_address, path, user_agent, method, res.status().code);
 
         let conn = Connection::<Db>::from_request(req)
             .await
@@ -306,16 +323,17 @@ impl Fairing for Analytics {
 #[derive(Serialize)]
 struct Visits {
     pub path_id: i64,
+    pub site: String,
     pub path: String,
     pub unique_visitors: i64,
     pub total_requests: i64,
 }
 
 #[get("/visits/id/<id>")]
-async fn visits_id(mut db: Connection<Db>, id: i32) -> Result<Json<Visits>, Status> {
+async fn visits_id(mut db: Connection<Db>, site: &Site, id: i32) -> Result<Json<Visits>, Status> {
 
     let unique_result =
-        sqlx::query_as!(Visits, "SELECT * FROM paths WHERE path_id = $1 LIMIT 1", id)
+        sqlx::query_as!(Visits, "SELECT * FROM paths WHERE site = $1 AND path_id = $2 LIMIT 1", site.config.name, id)
             .fetch_optional(&mut **db)
             .await;
 
@@ -335,7 +353,7 @@ async fn visits_id(mut db: Connection<Db>, id: i32) -> Result<Json<Visits>, Stat
 }
 
 #[get("/visits/path/<path>")]
-async fn visits_path(mut db: Connection<Db>, path: String) -> Result<Json<Visits>, Status> {
+async fn visits_path(mut db: Connection<Db>, site: &Site, path: String) -> Result<Json<Visits>, Status> {
     use rocket::http::RawStr;
     let path = RawStr::new(&path).percent_decode();
 
@@ -345,7 +363,7 @@ async fn visits_path(mut db: Connection<Db>, path: String) -> Result<Json<Visits
     };
 
     let unique_result =
-        sqlx::query_as!(Visits, "SELECT * FROM paths WHERE path = $1 LIMIT 1", path)
+        sqlx::query_as!(Visits, "SELECT * FROM paths WHERE site = $1 AND path = $2 LIMIT 1", site.config.name, path)
             .fetch_optional(&mut **db)
             .await;
 
@@ -386,9 +404,21 @@ impl DurationExt for Duration {
 }
 
 #[get("/path/id/<id>")]
-async fn path_view(mut conn: Connection<Db>, id: u32) -> Result<Template, Status> {
+async fn path_view(mut conn: Connection<Db>, site: &Site, id: u32) -> Result<Template, Status> {
     use std::time::Duration;
 
+    // ids are shared between sites, only show this site's own paths
+    let owned = sqlx::query!("SELECT path_id FROM paths WHERE site = $1 AND path_id = $2", site.config.name, id)
+        .fetch_optional(&mut **conn)
+        .await;
+    match owned {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(Status::NotFound),
+        Err(x) => {
+            error!("failed to look up path {}: {}", id, x);
+            return Err(Status::InternalServerError);
+        }
+    }
     
 
     let duration = Duration::from_mins(30).as_millis();
@@ -412,13 +442,14 @@ async fn path_view(mut conn: Connection<Db>, id: u32) -> Result<Template, Status
 }
 
 #[get("/<page>")]
-async fn analytics_page_view(mut db: Connection<Db>, page: u32) -> Result<Template, Status> {
+async fn analytics_page_view(mut db: Connection<Db>, site: &Site, page: u32) -> Result<Template, Status> {
 
     let page_limit = 15;
     let ofset = page_limit * (page-1);
 
     let amount = sqlx::query!(
-        "SELECT COUNT(1) as count FROM paths"
+        "SELECT COUNT(1) as count FROM paths WHERE site = $1",
+        site.config.name
     )
     .fetch_one(&mut **db)
     .await
@@ -432,8 +463,8 @@ async fn analytics_page_view(mut db: Connection<Db>, page: u32) -> Result<Templa
     
     let routes = sqlx::query_as!(
         Visits,
-        "SELECT * FROM paths ORDER BY total_requests DESC LIMIT $1 OFFSET $2",
-        page_limit, ofset
+        "SELECT * FROM paths WHERE site = $1 ORDER BY total_requests DESC LIMIT $2 OFFSET $3",
+        site.config.name, page_limit, ofset
     )
     .fetch_all(&mut **db)
     .await;
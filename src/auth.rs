@@ -0,0 +1,160 @@
+use base64::Engine;
+use git2::{CertificateCheckStatus, Cred, CredentialType, RemoteCallbacks};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::fs;
+use std::path::Path;
+
+use crate::config::Credentials;
+
+/// callbacks that answer credential requests and check ssh host keys
+/// according to `creds`, used for both the clone and later fetches
+pub fn callbacks(creds: &Credentials) -> RemoteCallbacks<'_> {
+    let mut cb = RemoteCallbacks::new();
+
+    // libgit2 keeps asking for as long as we keep handing out credentials,
+    // so offer each method once and then give up
+    let (mut tried_agent, mut tried_key, mut tried_token) = (false, false, false);
+    cb.credentials(move |url, username_from_url, allowed| {
+        let username = username_from_url
+            .or(creds.username.as_deref())
+            .unwrap_or("git");
+
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username);
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            if creds.ssh_agent && !tried_agent {
+                tried_agent = true;
+                return Cred::ssh_key_from_agent(username);
+            }
+            if let (Some(key), false) = (&creds.ssh_key, tried_key) {
+                tried_key = true;
+                return Cred::ssh_key(
+                    username,
+                    creds.ssh_public_key.as_deref(),
+                    key,
+                    creds.ssh_passphrase.as_deref(),
+                );
+            }
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !tried_token {
+            tried_token = true;
+            if let Some(token) = token(creds) {
+                return Cred::userpass_plaintext(username, &token);
+            }
+        }
+        Err(git2::Error::from_str(&format!(
+            "no usable credentials for {}",
+            url
+        )))
+    });
+
+    if let Some(known_hosts) = &creds.known_hosts {
+        cb.certificate_check(move |cert, host| {
+            let hostkey = match cert.as_hostkey() {
+                Some(x) => x,
+                // tls certificates are left to the normal verification
+                None => return Ok(CertificateCheckStatus::CertificatePassthrough),
+            };
+            let (key, key_type) = match (hostkey.hostkey(), hostkey.hostkey_type()) {
+                (Some(key), Some(key_type)) => (key, key_type),
+                _ => return Err(git2::Error::from_str("remote sent no host key")),
+            };
+            if is_known_host(known_hosts, host, key_type.name(), key) {
+                Ok(CertificateCheckStatus::CertificateOk)
+            } else {
+                Err(git2::Error::from_str(&format!(
+                    "host key for {} is not in {}",
+                    host,
+                    known_hosts.display()
+                )))
+            }
+        });
+    }
+
+    cb
+}
+
+/// an Authorization header for https requests made alongside git's own,
+/// like Git LFS downloads
+pub fn http_authorization(creds: &Credentials) -> Option<String> {
+    let token = token(creds)?;
+    let username = creds.username.as_deref().unwrap_or("git");
+    let encoded =
+        base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, token));
+    Some(format!("Basic {}", encoded))
+}
+
+/// the https token, read fresh each time so a rotated token is picked up
+fn token(creds: &Credentials) -> Option<String> {
+    if let Some(var) = &creds.token_env {
+        if let Ok(token) = std::env::var(var) {
+            return Some(token);
+        }
+    }
+    if let Some(file) = &creds.token_file {
+        match fs::read_to_string(file) {
+            Ok(token) => return Some(token.trim().to_string()),
+            Err(e) => warn!("failed to read token file {}: {}", file.display(), e),
+        }
+    }
+    creds.token.clone()
+}
+
+/// whether `pattern` from a known_hosts line names `host`, handles plain
+/// names, `[host]:port` and hashed `|1|salt|hash` entries
+fn host_matches(pattern: &str, host: &str) -> bool {
+    if let Some(hashed) = pattern.strip_prefix("|1|") {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let (salt, hash) = match hashed.split_once('|') {
+            Some(x) => x,
+            None => return false,
+        };
+        let (salt, hash) = match (engine.decode(salt), engine.decode(hash)) {
+            (Ok(salt), Ok(hash)) => (salt, hash),
+            _ => return false,
+        };
+        let mut mac = match Hmac::<Sha1>::new_from_slice(&salt) {
+            Ok(x) => x,
+            Err(_) => return false,
+        };
+        mac.update(host.as_bytes());
+        return mac.verify_slice(&hash).is_ok();
+    }
+
+    pattern.split(',').any(|name| {
+        let name = match name.strip_prefix('[') {
+            Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+            None => name,
+        };
+        name.eq_ignore_ascii_case(host)
+    })
+}
+
+fn is_known_host(known_hosts: &Path, host: &str, key_type: &str, key: &[u8]) -> bool {
+    let contents = match fs::read_to_string(known_hosts) {
+        Ok(x) => x,
+        Err(e) => {
+            error!("failed to read {}: {}", known_hosts.display(), e);
+            return false;
+        }
+    };
+    let engine = base64::engine::general_purpose::STANDARD;
+
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            // revoked keys and cert authorities aren't supported
+            let hosts = fields.next().filter(|x| !x.starts_with('@'))?;
+            Some((hosts, fields.next()?, fields.next()?))
+        })
+        .any(|(hosts, line_type, line_key)| {
+            line_type == key_type
+                && host_matches(hosts, host)
+                && engine.decode(line_key).is_ok_and(|x| x == key)
+        })
+}
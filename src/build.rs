@@ -0,0 +1,97 @@
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::config::BuildConfig;
+use crate::deploy::DeployError;
+
+/// keep only the end of very chatty build output, that's where the errors are
+const MAX_LOG: usize = 64 * 1024;
+
+/// runs the site generator in a freshly exported release and returns its
+/// output. a failing or timed out build fails the deploy so it's never
+/// made live
+pub fn run(build: &BuildConfig, dir: &Path) -> Result<String, DeployError> {
+    // the path goes through the environment so the shell never parses it
+    let out = dir.join(&build.output_dir);
+    let command = build.command.replace("{out}", "\"$OUT\"");
+    info!("building release in {}: {}", dir.display(), command);
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .env("OUT", &out)
+        .current_dir(dir)
+        // its own process group so a timeout takes down everything it started
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| DeployError::Build {
+            error: format!("failed to start `{}`: {}", command, e),
+            log: String::new(),
+            exited: false,
+        })?;
+
+    // drain both pipes on their own threads so a full pipe can't stall the
+    // build while we're waiting on it
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let status = wait(&mut child, Duration::from_secs(build.timeout));
+
+    let mut log = format!(
+        "$ {}\n{}{}",
+        command,
+        stdout.join().unwrap_or_default(),
+        stderr.join().unwrap_or_default()
+    );
+    if log.len() > MAX_LOG {
+        let mut start = log.len() - MAX_LOG;
+        while !log.is_char_boundary(start) {
+            start += 1;
+        }
+        log = format!("[log truncated]\n{}", &log[start..]);
+    }
+
+    let (error, exited) = match status {
+        Ok(Some(status)) if status.success() => return Ok(log),
+        Ok(Some(status)) => (format!("build exited with {}", status), true),
+        Ok(None) => (format!("build timed out after {}s", build.timeout), false),
+        Err(e) => (format!("failed to wait on build: {}", e), false),
+    };
+    Err(DeployError::Build { error, log, exited })
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        String::from_utf8_lossy(&buf).into_owned()
+    })
+}
+
+/// waits for the build to exit, killing it once `timeout` has passed.
+/// `None` means it was killed
+fn wait(child: &mut Child, timeout: Duration) -> std::io::Result<Option<std::process::ExitStatus>> {
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if started.elapsed() > timeout {
+            let group = format!("-{}", child.id());
+            let killed = Command::new("kill").args(["-KILL", "--", &group]).status();
+            if !killed.is_ok_and(|x| x.success()) {
+                child.kill()?;
+            }
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,143 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rocket::http::ContentType;
+use rocket::Request;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::CompressionConfig;
+
+/// whether the file's type is worth compressing, images, fonts and the
+/// like are compressed already
+pub fn compressible(path: &Path) -> bool {
+    let content_type = match path
+        .extension()
+        .and_then(|x| x.to_str())
+        .and_then(ContentType::from_extension)
+    {
+        Some(x) => x,
+        None => return false,
+    };
+    let sub = content_type.sub().as_str();
+    match content_type.top().as_str() {
+        "text" => true,
+        "application" => {
+            matches!(
+                sub,
+                "javascript" | "json" | "xml" | "wasm" | "manifest+json"
+            ) || sub.ends_with("+xml")
+        }
+        "image" => sub == "svg+xml",
+        _ => false,
+    }
+}
+
+/// whether the request's Accept-Encoding allows `coding`
+pub fn accepts(req: &Request<'_>, coding: &str) -> bool {
+    let header = match req.headers().get_one("Accept-Encoding") {
+        Some(x) => x,
+        None => return false,
+    };
+    let mut wildcard = false;
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let q = parts
+            .find_map(|x| x.trim().strip_prefix("q="))
+            .and_then(|x| x.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(coding) {
+            return q > 0.0;
+        }
+        if name == "*" {
+            wildcard = q > 0.0;
+        }
+    }
+    wildcard
+}
+
+/// `path` with `.suffix` on the end, where a precompressed copy is kept
+pub fn variant(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+pub fn gzip(data: &[u8], level: Compression) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), level);
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// writes a `.gz` next to every compressible file in `dir` that's big
+/// enough and doesn't have one yet, so it's compressed once per release
+/// rather than on every request
+pub fn precompress(dir: &Path, config: &CompressionConfig) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            precompress(&path, config)?;
+            continue;
+        }
+        if !file_type.is_file()
+            || !compressible(&path)
+            || entry.metadata()?.len() < config.min_size
+            || variant(&path, "gz").exists()
+        {
+            continue;
+        }
+        let data = fs::read(&path)?;
+        let compressed = gzip(&data, Compression::best())?;
+        // some files just don't get any smaller
+        if compressed.len() < data.len() {
+            fs::write(variant(&path, "gz"), compressed)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::blocking::Client;
+
+    fn accepted(header: Option<&str>) -> Vec<&'static str> {
+        let client = Client::untracked(rocket::build()).unwrap();
+        let mut req = client.get("/");
+        if let Some(header) = header {
+            req.add_header(rocket::http::Header::new(
+                "Accept-Encoding",
+                header.to_string(),
+            ));
+        }
+        ["br", "gzip", "identity"]
+            .into_iter()
+            .filter(|x| accepts(&req, x))
+            .collect()
+    }
+
+    #[test]
+    fn reads_accept_encoding() {
+        assert!(accepted(None).is_empty());
+        assert_eq!(accepted(Some("gzip, deflate")), ["gzip"]);
+        assert_eq!(accepted(Some("GZIP;q=0.5, br")), ["br", "gzip"]);
+        assert_eq!(accepted(Some("identity")), ["identity"]);
+    }
+
+    #[test]
+    fn q_of_zero_refuses() {
+        assert_eq!(accepted(Some("br;q=0, gzip")), ["gzip"]);
+        assert_eq!(accepted(Some("gzip; q=0.0")), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn wildcards_cover_the_rest() {
+        assert_eq!(accepted(Some("*")), ["br", "gzip", "identity"]);
+        assert_eq!(accepted(Some("br;q=0, *")), ["gzip", "identity"]);
+        assert_eq!(accepted(Some("gzip, *;q=0")), ["gzip"]);
+    }
+}
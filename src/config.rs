@@ -0,0 +1,445 @@
+use rocket::figment::Figment;
+use rocket::serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::pull;
+
+/// how a fetched commit is applied to the checkout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum DeployStrategy {
+    /// force the working tree to exactly match upstream, dropping any
+    /// local changes and untracked files
+    #[default]
+    Reset,
+    /// merge upstream into the local branch, creating merge commits when
+    /// the histories have diverged
+    Merge,
+}
+
+/// how to authenticate to the site repository, anything left unset is
+/// simply not offered to the remote
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Credentials {
+    /// private key used for ssh remotes
+    pub ssh_key: Option<PathBuf>,
+    /// public half of `ssh_key`, only needed if it can't be derived
+    pub ssh_public_key: Option<PathBuf>,
+    pub ssh_passphrase: Option<String>,
+    /// try keys from a running ssh-agent before `ssh_key`
+    pub ssh_agent: bool,
+    /// username for https remotes, or for ssh when the url doesn't name one
+    pub username: Option<String>,
+    /// https token or password, `token_env` and `token_file` are preferred
+    /// so it doesn't end up in Rocket.toml
+    pub token: Option<String>,
+    /// name of an environment variable holding the token
+    pub token_env: Option<String>,
+    /// file holding the token
+    pub token_file: Option<PathBuf>,
+    /// when set, ssh host keys must be listed in this known_hosts file
+    pub known_hosts: Option<PathBuf>,
+}
+
+/// a static site generator run over each release before it goes live
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BuildConfig {
+    /// run with `sh -c` in the release, `{out}` and `$OUT` are the full
+    /// path of `output_dir`
+    pub command: String,
+    /// where the generator writes the site, relative to the release. this
+    /// is what gets served instead of `public_dir`
+    #[serde(default = "default_public_dir")]
+    pub output_dir: PathBuf,
+    /// seconds before the build is killed and the deploy failed
+    #[serde(default = "default_build_timeout")]
+    pub timeout: u64,
+}
+
+/// keys trusted to sign the commits that get deployed
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SignatureConfig {
+    /// ssh allowed signers file, in the format of git's
+    /// `gpg.ssh.allowedSignersFile`
+    pub allowed_signers: Option<PathBuf>,
+    /// keyring of trusted OpenPGP keys, as written by `gpg --export`
+    pub gpg_keyring: Option<PathBuf>,
+}
+
+/// where Git LFS objects come from
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct LfsConfig {
+    /// the LFS server, worked out from `repo` like git-lfs does when unset
+    pub endpoint: Option<String>,
+    /// a directory laid out like `.git/lfs/objects` that's checked before
+    /// the server, handy for testing
+    pub store: Option<PathBuf>,
+}
+
+/// per-branch preview deployments, served on `<branch>.preview.<domain>`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PreviewConfig {
+    /// branches that get a preview, `*` matches any run of characters
+    /// including `/` and `?` any single one. the deployed branch never does
+    #[serde(default = "default_preview_branches")]
+    pub branches: String,
+    /// defaults to the site's first hostname
+    pub domain: Option<String>,
+    /// holds each preview's release, defaults to `./sites/<name>/previews`
+    #[serde(default)]
+    pub dir: PathBuf,
+}
+
+/// Cache-Control headers sent with the site's files
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CacheConfig {
+    /// for files with a hash of their content in the name like
+    /// `app.3f9a2c1b.js`, a new version gets a new name so these can be
+    /// kept for good
+    pub fingerprinted: Option<String>,
+    /// by extension without the dot, `*` covers the rest. setting this
+    /// replaces the defaults rather than adding to them
+    pub extensions: BTreeMap<String, String>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        let page = "public, max-age=60".to_string();
+        Self {
+            fingerprinted: Some("public, max-age=31536000, immutable".to_string()),
+            extensions: BTreeMap::from([
+                ("html".to_string(), page.clone()),
+                ("htm".to_string(), page),
+                ("*".to_string(), "public, max-age=3600".to_string()),
+            ]),
+        }
+    }
+}
+
+/// gzip for the site's text files
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CompressionConfig {
+    /// write a `.gz` next to each compressible file when a release is made
+    pub precompress: bool,
+    /// gzip compressible files as they're sent when there's no precompressed
+    /// `.br` or `.gz` next to them
+    pub on_the_fly: bool,
+    /// files smaller than this many bytes aren't worth compressing
+    pub min_size: u64,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            precompress: true,
+            on_the_fly: true,
+            min_size: 1024,
+        }
+    }
+}
+
+/// whether page urls end in a slash
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum TrailingSlash {
+    /// `/posts/hello/`, redirecting from `/posts/hello`
+    Always,
+    /// `/posts/hello`, redirecting from `/posts/hello/`
+    Never,
+    /// either works, as the link was written
+    #[default]
+    Preserve,
+}
+
+/// how the site's pages are addressed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct UrlConfig {
+    /// serve `posts/hello.html` at `/posts/hello`, and redirect there from
+    /// the url with `.html` or `/index.html` on the end
+    pub clean: bool,
+    pub trailing_slash: TrailingSlash,
+}
+
+impl Default for UrlConfig {
+    fn default() -> Self {
+        Self {
+            clean: true,
+            trailing_slash: TrailingSlash::default(),
+        }
+    }
+}
+
+/// a url told about every deploy, like a chat or CI webhook
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NotifyConfig {
+    pub url: String,
+    /// signs the body with HMAC-SHA256 in `X-Bloghoster-Signature-256`,
+    /// formatted like GitHub's `X-Hub-Signature-256`
+    pub secret: Option<String>,
+    /// further attempts when the url can't be reached or answers with a
+    /// server error, each waiting twice as long as the last
+    #[serde(default = "default_notify_retries")]
+    pub retries: u32,
+}
+
+/// a site being hosted, read from the `sites` list of Rocket.toml or
+/// `ROCKET_SITES` in the environment
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SiteConfig {
+    /// identifies the site in analytics and the deploy history, and names
+    /// its directory under `./sites` unless the paths below are given
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// Host headers this site answers to, a site with none is served for
+    /// any host no other site claims
+    #[serde(default)]
+    pub hostnames: Vec<String>,
+    /// url of the git repository to clone the site from
+    pub repo: String,
+    /// branch that gets deployed
+    #[serde(default = "default_branch")]
+    pub branch: String,
+    /// deploy the newest tag matching this pattern, like `v*`, instead of
+    /// the tip of `branch`. pushes to branches are ignored while it's set
+    pub tags: Option<String>,
+    #[serde(default)]
+    pub credentials: Credentials,
+    /// replace Git LFS pointers with the real files in each release, they're
+    /// served as they are unless this is set. only the site repository's
+    /// own pointers are, not those inside its submodules
+    pub lfs: Option<LfsConfig>,
+    /// only deploy commits signed by one of these keys, unsigned and
+    /// untrusted commits fail the deploy. off unless configured
+    pub signatures: Option<SignatureConfig>,
+    /// where the repository is checked out
+    #[serde(default)]
+    pub checkout_dir: PathBuf,
+    /// subdirectory of the checkout that is served
+    #[serde(default = "default_public_dir")]
+    pub public_dir: PathBuf,
+    /// only fetch this many commits of history, on the first clone and on
+    /// every fetch after it. best used with the reset strategy since a
+    /// merge needs the history back to where the branches split
+    #[serde(default)]
+    pub depth: Option<u32>,
+    /// only check out and release these paths of the repository, like
+    /// `public/`. everything is when it's empty. without a build they have
+    /// to include `public_dir`, with one only what the build reads
+    #[serde(default)]
+    pub sparse_paths: Vec<String>,
+    /// each deployed commit is exported to its own directory in here
+    #[serde(default)]
+    pub releases_dir: PathBuf,
+    /// symlink to the release being served, swapped on each deploy
+    #[serde(default)]
+    pub live_dir: PathBuf,
+    /// deploy other branches as previews, off unless configured
+    pub preview: Option<PreviewConfig>,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    #[serde(default)]
+    pub urls: UrlConfig,
+    /// the page sent with an error status by its path in the served
+    /// directory, keyed by the status like `404 = "missing.html"`. statuses
+    /// left out use `404.html` and so on
+    #[serde(default)]
+    pub error_pages: BTreeMap<String, PathBuf>,
+    /// how many releases to keep on disk
+    #[serde(default = "default_keep_releases")]
+    pub keep_releases: usize,
+    /// shared secret used to verify refresh webhooks, refresh is refused
+    /// entirely when this is unset
+    #[serde(default)]
+    pub webhook_secret: Option<String>,
+    #[serde(default)]
+    pub strategy: DeployStrategy,
+    #[serde(default)]
+    pub build: Option<BuildConfig>,
+    /// seconds between checks of the remote for new commits, for hosts that
+    /// can't send webhooks. polling is off when unset
+    #[serde(default)]
+    pub poll_interval: Option<u64>,
+    /// POST a JSON report of each deploy, successful or not, to these
+    #[serde(default)]
+    pub notify: Vec<NotifyConfig>,
+    /// bearer token for the admin routes, they're disabled when unset
+    #[serde(default)]
+    pub admin_token: Option<String>,
+}
+
+fn default_branch() -> String {
+    "main".to_string()
+}
+
+fn default_name() -> String {
+    "default".to_string()
+}
+
+fn default_public_dir() -> PathBuf {
+    PathBuf::from("public")
+}
+
+fn default_preview_branches() -> String {
+    "*".to_string()
+}
+
+fn default_keep_releases() -> usize {
+    5
+}
+
+fn default_build_timeout() -> u64 {
+    10 * 60
+}
+
+fn default_notify_retries() -> u32 {
+    3
+}
+
+/// reads every configured site, falling back to the single `site` table
+/// older configs use
+pub fn load_sites(figment: &Figment) -> Result<Vec<SiteConfig>, String> {
+    let sites = if figment.find_value("sites").is_ok() {
+        figment.extract_inner::<Vec<SiteConfig>>("sites")
+    } else {
+        figment.extract_inner::<SiteConfig>("site").map(|x| vec![x])
+    };
+    let sites: Vec<SiteConfig> = sites
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(SiteConfig::with_default_dirs)
+        .collect();
+
+    for (i, site) in sites.iter().enumerate() {
+        if sites[..i].iter().any(|x| x.name == site.name) {
+            return Err(format!("more than one site is named {}", site.name));
+        }
+        // a host can only be served by one of them
+        for hostname in &site.hostnames {
+            let other = sites[..i]
+                .iter()
+                .find(|x| x.hostnames.iter().any(|x| x.eq_ignore_ascii_case(hostname)));
+            if let Some(other) = other {
+                return Err(format!(
+                    "sites {} and {} both claim the hostname {}",
+                    other.name, site.name, hostname
+                ));
+            }
+        }
+    }
+    for site in &sites {
+        let domain = site.preview.as_ref().map(|x| x.domain.is_some());
+        if domain == Some(false) {
+            return Err(format!(
+                "site {} has previews but no domain or hostnames to serve them on",
+                site.name
+            ));
+        }
+        // every deploy would otherwise go through and publish nothing. a
+        // build writes its output_dir itself, so only its inputs are checked out
+        if site.build.is_some() {
+            continue;
+        }
+        let served: PathBuf = site
+            .public_dir
+            .components()
+            .filter(|x| *x != Component::CurDir)
+            .collect();
+        if !served.as_os_str().is_empty() && !pull::checks_out(&site.sparse_paths, &served) {
+            return Err(format!(
+                "site {} serves {} but its sparse_paths don't include it",
+                site.name,
+                served.display()
+            ));
+        }
+    }
+    if sites.is_empty() {
+        return Err("no sites are configured".to_string());
+    }
+    Ok(sites)
+}
+
+impl SiteConfig {
+    /// puts any directory that wasn't configured under `./sites/<name>`
+    /// and serves previews under the first hostname unless told otherwise
+    fn with_default_dirs(mut self) -> Self {
+        let base = PathBuf::from("./sites").join(&self.name);
+        if self.checkout_dir.as_os_str().is_empty() {
+            self.checkout_dir = base.join("checkout");
+        }
+        if self.releases_dir.as_os_str().is_empty() {
+            self.releases_dir = base.join("releases");
+        }
+        if self.live_dir.as_os_str().is_empty() {
+            self.live_dir = base.join("live");
+        }
+        if let Some(preview) = &mut self.preview {
+            if preview.dir.as_os_str().is_empty() {
+                preview.dir = base.join("previews");
+            }
+            if preview.domain.is_none() {
+                preview.domain = self.hostnames.first().cloned();
+            }
+        }
+        self
+    }
+
+    /// the part of a release that gets served
+    pub fn served_dir(&self) -> &Path {
+        match &self.build {
+            Some(build) => &build.output_dir,
+            None => &self.public_dir,
+        }
+    }
+
+    /// the directory handed to the file server
+    pub fn public_path(&self) -> PathBuf {
+        self.live_dir.join(self.served_dir())
+    }
+
+    /// where the page for an error status is in the served directory
+    pub fn error_page(&self, status: u16) -> PathBuf {
+        match self.error_pages.get(&status.to_string()) {
+            Some(x) => x.clone(),
+            None => PathBuf::from(format!("{}.html", status)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::figment::providers::{Format, Toml};
+
+    fn load(toml: &str) -> Result<Vec<SiteConfig>, String> {
+        load_sites(&Figment::from(Toml::string(toml)))
+    }
+
+    #[test]
+    fn sparse_paths_have_to_include_public_dir() {
+        let sites = load("[site]\nrepo = \"x\"\nsparse_paths = [\"public/\"]\n");
+        assert!(sites.is_ok(), "{:?}", sites);
+        let sites = load("[site]\nrepo = \"x\"\nsparse_paths = [\"content/\"]\n");
+        assert!(sites.is_err());
+    }
+
+    #[test]
+    fn sparse_paths_of_a_build_only_need_its_inputs() {
+        let sites = load(
+            "[site]\nrepo = \"x\"\nsparse_paths = [\"content/\", \"templates/\", \"config.toml\"]\n\n[site.build]\ncommand = \"zola build\"\noutput_dir = \"public\"\n",
+        );
+        assert!(sites.is_ok(), "{:?}", sites);
+    }
+}
@@ -0,0 +1,743 @@
+use git2::{Oid, Repository};
+use rocket::fairing::AdHoc;
+use rocket::serde::Serialize;
+use rocket::tokio;
+use rocket::tokio::sync::Notify;
+use rocket_db_pools::Database;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::analytics::Db;
+use crate::auth;
+use crate::build;
+use crate::compress;
+use crate::config::{DeployStrategy, SiteConfig};
+use crate::gone;
+use crate::headers;
+use crate::lfs;
+use crate::notify::{self, DeployEvent};
+use crate::poll;
+use crate::preview;
+use crate::pull;
+use crate::redirects;
+use crate::release;
+use crate::sites::Sites;
+use crate::submodules;
+use crate::tags;
+use crate::verify;
+
+#[derive(Debug)]
+pub enum DeployError {
+    Clone(git2::Error),
+    Fetch(git2::Error),
+    Merge(git2::Error),
+    Checkout(git2::Error),
+    /// upstream can't be merged into the checkout without conflicts
+    Conflict,
+    /// a submodule couldn't be fetched or checked out
+    Submodule {
+        path: String,
+        error: git2::Error,
+    },
+    /// Git LFS objects couldn't be fetched
+    Lfs(String),
+    /// no tag matches the configured pattern
+    NoTag(String),
+    /// the commit isn't signed by a trusted key, and why
+    Signature(String),
+    /// the release directory couldn't be written or switched to
+    Release(std::io::Error),
+    /// the site generator failed or timed out, `log` is its output.
+    /// `exited` is set when it ran to the end and reported failure
+    Build {
+        error: String,
+        log: String,
+        exited: bool,
+    },
+    /// the blocking deploy task died before it could report back
+    Aborted(String),
+}
+
+impl std::fmt::Display for DeployError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeployError::Clone(e) => write!(f, "failed to clone: {}", e),
+            DeployError::Fetch(e) => write!(f, "failed to fetch: {}", e),
+            DeployError::Merge(e) => write!(f, "failed to merge: {}", e),
+            DeployError::Checkout(e) => write!(f, "failed to check out: {}", e),
+            DeployError::Conflict => write!(f, "upstream conflicts with the checkout"),
+            DeployError::Submodule { path, error } => {
+                write!(f, "failed to check out submodule {}: {}", path, error)
+            }
+            DeployError::Lfs(e) => write!(f, "failed to fetch Git LFS objects: {}", e),
+            DeployError::NoTag(pattern) => write!(f, "no tag matches {}", pattern),
+            DeployError::Signature(e) => write!(f, "refused to deploy {}", e),
+            DeployError::Release(e) => write!(f, "failed to publish release: {}", e),
+            DeployError::Build { error, .. } => write!(f, "build failed: {}", error),
+            DeployError::Aborted(e) => write!(f, "deploy aborted: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DeployError {}
+
+impl DeployError {
+    /// sorts an error out of the merge step, which can fail while merging
+    /// or while writing the result to the working tree
+    fn from_merge(e: git2::Error) -> Self {
+        if e.code() == git2::ErrorCode::MergeConflict {
+            DeployError::Conflict
+        } else if e.class() == git2::ErrorClass::Checkout {
+            DeployError::Checkout(e)
+        } else {
+            DeployError::Merge(e)
+        }
+    }
+
+    /// whether deploying the same commit again would fail the same way.
+    /// anything else, like the remote or a download going away halfway,
+    /// may well pass on another try
+    pub fn is_deterministic(&self) -> bool {
+        matches!(
+            self,
+            DeployError::Conflict
+                | DeployError::Signature(_)
+                | DeployError::Build { exited: true, .. }
+        )
+    }
+}
+
+/// what ended up live after a deploy or rollback
+#[derive(Debug, Clone)]
+pub struct Deployed {
+    pub commit: git2::Oid,
+    pub message: Option<String>,
+    pub author: Option<String>,
+    /// objects downloaded by the fetch, `None` when nothing was fetched
+    pub objects_received: Option<usize>,
+    /// output of the site generator, `None` when nothing was built
+    pub build_log: Option<String>,
+}
+
+impl Deployed {
+    fn new(commit: &git2::Commit) -> Self {
+        Self {
+            commit: commit.id(),
+            message: commit.message().map(|x| x.trim().to_string()),
+            author: commit.author().name().map(|x| x.to_string()),
+            objects_received: None,
+            build_log: None,
+        }
+    }
+}
+
+/// brings the checkout up to date with the configured branch, publishes
+/// it as a new release and returns the commit that is now live. on error
+/// the live symlink is left alone so the last good release keeps being
+/// served. `attempted` is set to the commit being deployed once it's known
+pub fn git_refresh(
+    site: &SiteConfig,
+    attempted: &mut Option<Oid>,
+) -> Result<Deployed, DeployError> {
+    let repo = match Repository::open(&site.checkout_dir) {
+        Ok(repo) => repo,
+        Err(_e) => pull::clone(
+            &site.repo,
+            &site.branch,
+            &site.checkout_dir,
+            auth::callbacks(&site.credentials),
+            site.depth,
+            &site.sparse_paths,
+        )
+        .map_err(DeployError::Clone)?,
+    };
+
+    //git pull
+    let remote_branch = site.branch.as_str();
+    let mut remote = repo.find_remote("origin").map_err(DeployError::Fetch)?;
+    let callbacks = auth::callbacks(&site.credentials);
+    let fetch_commit = pull::do_fetch(&repo, &[remote_branch], &mut remote, callbacks, site.depth)
+        .map_err(DeployError::Fetch)?;
+    let objects_received = remote.stats().received_objects();
+
+    let head = match &site.tags {
+        // the fetch brought every tag along, the release is exported from
+        // the tag so the checkout can stay where it is
+        Some(pattern) => match tags::newest(&repo, pattern).map_err(DeployError::Checkout)? {
+            Some((tag, commit)) => {
+                info!("deploying tag {}", tag);
+                *attempted = Some(commit.id());
+                verify::check(site, &repo, commit.id())?;
+                commit
+            }
+            None => return Err(DeployError::NoTag(pattern.clone())),
+        },
+        None => {
+            *attempted = Some(fetch_commit.id());
+            // checked before the checkout moves so a refused commit is
+            // never even written out
+            verify::check(site, &repo, fetch_commit.id())?;
+            match site.strategy {
+                DeployStrategy::Reset => {
+                    pull::hard_reset(&repo, remote_branch, &fetch_commit, &site.sparse_paths)
+                        .map_err(DeployError::Checkout)?
+                }
+                DeployStrategy::Merge => {
+                    pull::do_merge(&repo, remote_branch, fetch_commit, &site.sparse_paths)
+                        .map_err(DeployError::from_merge)?
+                }
+            }
+            repo.head()
+                .and_then(|x| x.peel_to_commit())
+                .map_err(DeployError::Checkout)?
+        }
+    };
+
+    let mut build_log = None;
+    let release_dir = release::export(
+        &repo,
+        &head,
+        &site.releases_dir,
+        &site.sparse_paths,
+        |dir| {
+            build_log = prepare_release(site, &repo, &head, dir)?;
+            Ok(())
+        },
+    )?;
+    release::activate(&release_dir, &site.live_dir).map_err(DeployError::Release)?;
+    if let Err(e) = release::prune(&site.releases_dir, &site.live_dir, site.keep_releases) {
+        warn!("failed to clean up old releases: {}", e);
+    }
+    Ok(Deployed {
+        objects_received: Some(objects_received),
+        build_log,
+        ..Deployed::new(&head)
+    })
+}
+
+/// points the live site back at an earlier commit, exporting and building
+/// its release again if it has since been pruned. the checkout itself
+/// isn't touched
+pub fn git_rollback(site: &SiteConfig, commit: &str) -> Result<Deployed, DeployError> {
+    let repo = Repository::open(&site.checkout_dir).map_err(DeployError::Checkout)?;
+    let commit = git2::Oid::from_str(commit)
+        .and_then(|x| repo.find_commit(x))
+        .map_err(DeployError::Checkout)?;
+
+    let mut build_log = None;
+    let release_dir = release::export(
+        &repo,
+        &commit,
+        &site.releases_dir,
+        &site.sparse_paths,
+        |dir| {
+            build_log = prepare_release(site, &repo, &commit, dir)?;
+            Ok(())
+        },
+    )?;
+    release::activate(&release_dir, &site.live_dir).map_err(DeployError::Release)?;
+    Ok(Deployed {
+        build_log,
+        ..Deployed::new(&commit)
+    })
+}
+
+/// fills in what a plain checkout of `commit` leaves out, submodules and
+/// LFS objects, runs the build, checks the `_redirects` and `_headers`
+/// files and compresses what will be served. returns the build's output
+pub fn prepare_release(
+    site: &SiteConfig,
+    repo: &Repository,
+    commit: &git2::Commit,
+    dir: &Path,
+) -> Result<Option<String>, DeployError> {
+    submodules::export(repo, commit, dir, &site.credentials, &site.sparse_paths)?;
+    lfs::resolve(site, repo, commit, dir)?;
+    let build_log = match &site.build {
+        Some(build) => Some(build::run(build, dir)?),
+        None => None,
+    };
+    let served = dir.join(site.served_dir());
+    redirects::check(&served);
+    headers::check(&served);
+    gone::check(&served);
+    if site.compression.precompress && served.is_dir() {
+        compress::precompress(&served, &site.compression).map_err(DeployError::Release)?;
+    }
+    Ok(build_log)
+}
+
+/// what caused a deploy, stored with each deploy record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Startup,
+    Webhook,
+    Rollback,
+    Poll,
+}
+
+impl Trigger {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Trigger::Startup => "startup",
+            Trigger::Webhook => "webhook",
+            Trigger::Rollback => "rollback",
+            Trigger::Poll => "poll",
+        }
+    }
+
+    /// explicit deploys go ahead even when a rollback has pinned the site
+    fn is_explicit(&self) -> bool {
+        !matches!(self, Trigger::Startup | Trigger::Poll)
+    }
+}
+
+/// how the most recent deploy went
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DeployOutcome {
+    pub deploy_id: u64,
+    /// the commit being served after the deploy
+    pub commit: Option<String>,
+    pub error: Option<String>,
+}
+
+/// how the most recent deploy went, without the error that can carry urls,
+/// paths and build output. that's only in the admin history
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DeployStatus {
+    pub deploy_id: u64,
+    pub commit: Option<String>,
+    pub succeeded: bool,
+}
+
+impl From<DeployOutcome> for DeployStatus {
+    fn from(outcome: DeployOutcome) -> Self {
+        Self {
+            deploy_id: outcome.deploy_id,
+            succeeded: outcome.error.is_none(),
+            commit: outcome.commit,
+        }
+    }
+}
+
+/// handed back to whoever asked for a deploy so they can find it again
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DeployTicket {
+    pub deploy_id: u64,
+}
+
+/// runs deploys one at a time on a background task so the request
+/// handlers never wait on libgit2
+pub struct Deployer {
+    site: SiteConfig,
+    /// shared between every site's deployer
+    next_id: Arc<AtomicU64>,
+    /// the deploy waiting to start, any requests that come in before it
+    /// starts are folded into it since they would fetch the same thing
+    pending: Mutex<Option<(u64, Trigger)>>,
+    /// set when the branch previews need to be synced with the remote
+    previews_stale: AtomicBool,
+    /// the commit last tried for each preview label, so a branch that
+    /// can't be deployed isn't built again on every sync
+    preview_attempts: Mutex<HashMap<String, Oid>>,
+    /// set when the last preview sync ran into something worth retrying
+    previews_failed: AtomicBool,
+    /// the upstream commit the last deploy went for, unless it failed in a
+    /// way another try might get past
+    attempted: Mutex<Option<Oid>>,
+    wake: Notify,
+    /// held for the whole fetch and merge so nothing else touches the
+    /// checkout while it's being updated
+    repo_lock: tokio::sync::Mutex<()>,
+    last: Mutex<Option<DeployOutcome>>,
+    /// set once the server has lifted off and the pool is available
+    db: OnceLock<SqlitePool>,
+}
+
+impl Deployer {
+    pub fn new(site: SiteConfig, next_id: Arc<AtomicU64>) -> Arc<Self> {
+        Arc::new(Self {
+            site,
+            next_id,
+            pending: Mutex::new(None),
+            previews_stale: AtomicBool::new(false),
+            preview_attempts: Mutex::new(HashMap::new()),
+            previews_failed: AtomicBool::new(false),
+            attempted: Mutex::new(None),
+            wake: Notify::new(),
+            repo_lock: tokio::sync::Mutex::new(()),
+            last: Mutex::new(None),
+            db: OnceLock::new(),
+        })
+    }
+
+    /// queues a deploy and returns straight away, if one is already
+    /// queued and hasn't started yet that one's id is returned instead
+    pub fn request(&self, trigger: Trigger) -> DeployTicket {
+        let mut pending = self.pending.lock().unwrap();
+        let deploy_id = match *pending {
+            Some((id, queued)) => {
+                if trigger.is_explicit() && !queued.is_explicit() {
+                    *pending = Some((id, trigger));
+                }
+                id
+            }
+            None => {
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                *pending = Some((id, trigger));
+                self.wake.notify_one();
+                id
+            }
+        };
+        DeployTicket { deploy_id }
+    }
+
+    /// queues a sync of the branch previews, which runs after any deploy
+    /// of the site itself
+    pub fn request_previews(&self) {
+        if !self.previews_stale.swap(true, Ordering::Relaxed) {
+            self.wake.notify_one();
+        }
+    }
+
+    /// queues a sync of the branch previews that tries again at branches
+    /// whose last deploy failed, for explicit requests
+    pub fn retry_previews(&self) {
+        self.preview_attempts.lock().unwrap().clear();
+        self.request_previews();
+    }
+
+    /// the upstream commit the last deploy tried to put live, polling
+    /// leaves one that can't be deployed alone until upstream moves on
+    pub fn last_attempted(&self) -> Option<Oid> {
+        *self.attempted.lock().unwrap()
+    }
+
+    /// whether the last preview sync failed on something that may pass
+    pub fn previews_failed(&self) -> bool {
+        self.previews_failed.load(Ordering::Relaxed)
+    }
+
+    /// the result of the last deploy to finish, `None` until one has
+    pub fn last_outcome(&self) -> Option<DeployOutcome> {
+        self.last.lock().unwrap().clone()
+    }
+
+    /// makes `commit` live again straight away, bypassing the queue. the
+    /// site stays on it until the next explicit deploy
+    pub async fn rollback(&self, commit: String) -> Result<Deployed, DeployError> {
+        let _guard = self.repo_lock.lock().await;
+        let deploy_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        info!("rolling back to {} as deploy {}", commit, deploy_id);
+
+        let started = Instant::now();
+        let site = self.site.clone();
+        let result = match tokio::task::spawn_blocking(move || git_rollback(&site, &commit)).await {
+            Ok(x) => x,
+            Err(e) => Err(DeployError::Aborted(e.to_string())),
+        };
+        self.finish(deploy_id, Trigger::Rollback, started, &result)
+            .await;
+        result
+    }
+
+    pub fn site(&self) -> &SiteConfig {
+        &self.site
+    }
+
+    /// keeps deploys from touching the checkout until the guard is dropped
+    pub async fn lock_repo(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.repo_lock.lock().await
+    }
+
+    /// whether the live site was last set by a rollback
+    pub async fn is_pinned(&self) -> bool {
+        let pool = match self.db.get() {
+            Some(x) => x,
+            None => return false,
+        };
+        let latest = sqlx::query!(
+            "SELECT trigger FROM deploys WHERE site = $1 AND succeeded = 1 ORDER BY deploy_id DESC LIMIT 1",
+            self.site.name
+        )
+        .fetch_optional(pool)
+        .await;
+        match latest {
+            Ok(x) => x.is_some_and(|x| x.trigger == Trigger::Rollback.as_str()),
+            Err(e) => {
+                error!("failed to read deploy history: {}", e);
+                false
+            }
+        }
+    }
+
+    /// records the result of a deploy in the history and as the last outcome
+    async fn finish(
+        &self,
+        deploy_id: u64,
+        trigger: Trigger,
+        started: Instant,
+        result: &Result<Deployed, DeployError>,
+    ) {
+        let duration = started.elapsed();
+        let outcome = match result {
+            Ok(deployed) => {
+                info!(
+                    "deploy {} finished at {} in {:?}",
+                    deploy_id, deployed.commit, duration
+                );
+                DeployOutcome {
+                    deploy_id,
+                    commit: Some(deployed.commit.to_string()),
+                    error: None,
+                }
+            }
+            Err(e) => {
+                error!(
+                    "deploy {} failed, still serving the previous checkout: {}",
+                    deploy_id, e
+                );
+                DeployOutcome {
+                    deploy_id,
+                    commit: None,
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+        self.record(deploy_id, trigger, duration, result).await;
+        self.notify(deploy_id, trigger, duration, &outcome);
+        *self.last.lock().unwrap() = Some(outcome);
+    }
+
+    /// tells the site's notify urls about the deploy in the background,
+    /// retries can take a while and shouldn't hold up the next deploy
+    fn notify(
+        &self,
+        deploy_id: u64,
+        trigger: Trigger,
+        duration: Duration,
+        outcome: &DeployOutcome,
+    ) {
+        if self.site.notify.is_empty() {
+            return;
+        }
+        let event = DeployEvent {
+            site: self.site.name.clone(),
+            deploy_id,
+            trigger: trigger.as_str(),
+            status: match outcome.error {
+                Some(_) => "failed",
+                None => "succeeded",
+            },
+            commit: outcome.commit.clone(),
+            duration_ms: duration.as_millis() as u64,
+            error: outcome.error.clone(),
+        };
+        let targets = self.site.notify.clone();
+        tokio::task::spawn_blocking(move || notify::send(&targets, &event));
+    }
+
+    async fn record(
+        &self,
+        deploy_id: u64,
+        trigger: Trigger,
+        duration: Duration,
+        result: &Result<Deployed, DeployError>,
+    ) {
+        use std::time::SystemTime;
+
+        let pool = match self.db.get() {
+            Some(x) => x,
+            None => return,
+        };
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let deploy_id = deploy_id as i64;
+        let trigger = trigger.as_str();
+        let duration_ms = duration.as_millis() as i64;
+
+        let (deployed, error) = match result {
+            Ok(x) => (Some(x), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let build_log = match result {
+            Ok(x) => x.build_log.clone(),
+            Err(DeployError::Build { log, .. }) => Some(log.clone()),
+            Err(_) => None,
+        };
+        let commit = deployed.map(|x| x.commit.to_string());
+        let message = deployed.and_then(|x| x.message.clone());
+        let author = deployed.and_then(|x| x.author.clone());
+        let objects_received = deployed.and_then(|x| x.objects_received).map(|x| x as i64);
+        let succeeded = result.is_ok();
+
+        let result = sqlx::query!(
+            "INSERT INTO deploys (deploy_id, site, commit_id, message, author, trigger, created_at, duration_ms, objects_received, succeeded, error, build_log) VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+            deploy_id, self.site.name, commit, message, author, trigger, time, duration_ms, objects_received, succeeded, error, build_log
+        )
+        .execute(pool)
+        .await;
+        if let Err(e) = result {
+            error!("failed to record deploy {}: {}", deploy_id, e);
+        }
+    }
+
+    /// keeps a deploy that was left alone for a pin in the history, so the
+    /// trigger doesn't just vanish
+    async fn record_skipped(&self, deploy_id: u64, trigger: Trigger) {
+        use std::time::SystemTime;
+
+        let pool = match self.db.get() {
+            Some(x) => x,
+            None => return,
+        };
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let deploy_id = deploy_id as i64;
+        let trigger = trigger.as_str();
+
+        let result = sqlx::query!(
+            "INSERT INTO deploys (deploy_id, site, trigger, created_at, succeeded, skipped) VALUES($1, $2, $3, $4, 0, 1)",
+            deploy_id, self.site.name, trigger, time
+        )
+        .execute(pool)
+        .await;
+        if let Err(e) = result {
+            error!("failed to record deploy {}: {}", deploy_id, e);
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        loop {
+            self.wake.notified().await;
+            let pending = self.pending.lock().unwrap().take();
+            if let Some((deploy_id, trigger)) = pending {
+                self.deploy(deploy_id, trigger).await;
+            }
+            if self.previews_stale.swap(false, Ordering::Relaxed) {
+                self.sync_previews().await;
+            }
+        }
+    }
+
+    async fn deploy(&self, deploy_id: u64, trigger: Trigger) {
+        let _guard = self.repo_lock.lock().await;
+        if !trigger.is_explicit() && self.is_pinned().await {
+            info!(
+                "skipping deploy {}, the site is pinned to a rollback",
+                deploy_id
+            );
+            self.record_skipped(deploy_id, trigger).await;
+            return;
+        }
+
+        info!("starting deploy {}", deploy_id);
+        let started = Instant::now();
+        let site = self.site.clone();
+        let refreshed = tokio::task::spawn_blocking(move || {
+            let mut attempted = None;
+            let result = git_refresh(&site, &mut attempted);
+            (result, attempted)
+        })
+        .await;
+        let result = match refreshed {
+            Ok((result, attempted)) => {
+                let retry = result.as_ref().is_err_and(|e| !e.is_deterministic());
+                if attempted.is_some() && !retry {
+                    *self.attempted.lock().unwrap() = attempted;
+                }
+                result
+            }
+            Err(e) => Err(DeployError::Aborted(e.to_string())),
+        };
+        self.finish(deploy_id, trigger, started, &result).await;
+    }
+
+    /// previews aren't part of the deploy history, failures are only logged
+    async fn sync_previews(&self) {
+        let _guard = self.repo_lock.lock().await;
+        let site = self.site.clone();
+        let mut attempts = std::mem::take(&mut *self.preview_attempts.lock().unwrap());
+        let synced = tokio::task::spawn_blocking(move || {
+            let result = preview::sync(&site, &mut attempts);
+            (result, attempts)
+        })
+        .await
+        .map(|(result, attempts)| {
+            *self.preview_attempts.lock().unwrap() = attempts;
+            result
+        });
+        let failed = match synced {
+            Ok(Ok(())) => false,
+            Ok(Err(e)) => {
+                error!("failed to sync previews of {}: {}", self.site.name, e);
+                true
+            }
+            Err(e) => {
+                error!("preview sync panicked: {}", e);
+                true
+            }
+        };
+        self.previews_failed.store(failed, Ordering::Relaxed);
+    }
+
+    /// starts each site's worker once the server is up and queues a deploy
+    /// to catch up with anything pushed while we were down, the existing
+    /// release is served in the meantime
+    pub fn fairing() -> AdHoc {
+        AdHoc::on_liftoff("Deploy Workers", |rocket| {
+            Box::pin(async move {
+                let sites = match rocket.state::<Sites>() {
+                    Some(x) => x,
+                    None => return error!("deploy workers started without any Sites in state"),
+                };
+
+                let pool = Db::fetch(rocket).map(|db| -> &SqlitePool { db });
+                if let Some(pool) = pool {
+                    // carry on numbering from the history so ids stay unique
+                    match sqlx::query!("SELECT MAX(deploy_id) as last FROM deploys")
+                        .fetch_one(pool)
+                        .await
+                    {
+                        Ok(x) => {
+                            let next = x.last.unwrap_or(0) as u64 + 1;
+                            for site in sites.iter() {
+                                site.deployer.next_id.fetch_max(next, Ordering::Relaxed);
+                            }
+                        }
+                        Err(e) => error!("failed to read deploy history: {}", e),
+                    }
+                }
+
+                for site in sites.iter() {
+                    let deployer = &site.deployer;
+                    if let Some(pool) = pool {
+                        let _ = deployer.db.set(pool.clone());
+                    }
+
+                    tokio::spawn(deployer.clone().run());
+                    deployer.request(Trigger::Startup);
+                    if deployer.site.preview.is_some() {
+                        deployer.request_previews();
+                    }
+
+                    if let Some(interval) = deployer.site.poll_interval {
+                        let interval = Duration::from_secs(interval.max(1));
+                        tokio::spawn(poll::run(deployer.clone(), interval));
+                    }
+                }
+            })
+        })
+    }
+}
@@ -0,0 +1,110 @@
+use rocket::fs::NamedFile;
+use rocket::http::Status;
+use rocket::response::content::RawHtml;
+use rocket::{Catcher, Request};
+
+use crate::sites::Sites;
+
+/// what's sent along with an error status
+#[derive(Responder)]
+pub enum ErrorPage {
+    /// the site's own page for the status
+    File(NamedFile),
+    /// for sites without one
+    Builtin(RawHtml<String>),
+}
+
+async fn page(status: Status, req: &Request<'_>) -> ErrorPage {
+    match site_page(status, req).await {
+        Some(file) => ErrorPage::File(file),
+        None => ErrorPage::Builtin(RawHtml(format!(
+            "<!DOCTYPE html>\n<html><head><title>{0}</title></head><body><h1>{0}</h1></body></html>\n",
+            status
+        ))),
+    }
+}
+
+/// the page configured for the status from the release being served
+async fn site_page(status: Status, req: &Request<'_>) -> Option<NamedFile> {
+    let sites = req.rocket().state::<Sites>()?;
+    let site = sites.for_request(req)?;
+    let page = site.config.error_page(status.code);
+    if let Some(path) = sites.served_path(req) {
+        if let Ok(file) = NamedFile::open(path.join(&page)).await {
+            return Some(file);
+        }
+    }
+    // previews that don't exist (or lack a page of their own) get the live one
+    let path = site.config.public_path().join(page);
+    NamedFile::open(path).await.ok()
+}
+
+#[catch(403)]
+async fn forbidden(req: &Request<'_>) -> ErrorPage {
+    page(Status::Forbidden, req).await
+}
+
+#[catch(404)]
+async fn not_found(req: &Request<'_>) -> ErrorPage {
+    page(Status::NotFound, req).await
+}
+
+#[catch(410)]
+async fn gone(req: &Request<'_>) -> ErrorPage {
+    page(Status::Gone, req).await
+}
+
+#[catch(500)]
+async fn internal_error(req: &Request<'_>) -> ErrorPage {
+    page(Status::InternalServerError, req).await
+}
+
+#[catch(503)]
+async fn unavailable(req: &Request<'_>) -> ErrorPage {
+    page(Status::ServiceUnavailable, req).await
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    catchers![forbidden, not_found, gone, internal_error, unavailable]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_sites;
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::figment::Figment;
+    use rocket::local::asynchronous::Client;
+
+    #[rocket::async_test]
+    async fn falls_back_to_the_builtin_page() {
+        let dir = std::env::temp_dir().join(format!("bloghoster-errors-{}", std::process::id()));
+        let public = dir.join("live").join("public");
+        std::fs::create_dir_all(&public).unwrap();
+        std::fs::write(public.join("404.html"), "the site's own 404").unwrap();
+
+        let toml = format!(
+            "[site]\nrepo = \"x\"\nlive_dir = \"{}\"\n",
+            dir.join("live").display()
+        );
+        let sites = load_sites(&Figment::from(Toml::string(&toml))).unwrap();
+        let rocket = rocket::build()
+            .manage(Sites::new(sites))
+            .register("/", catchers());
+        let client = Client::untracked(rocket).await.unwrap();
+
+        let res = client.get("/missing").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        assert_eq!(res.into_string().await.unwrap(), "the site's own 404");
+
+        std::fs::remove_file(public.join("404.html")).unwrap();
+        let res = client.get("/missing").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        assert!(res
+            .into_string()
+            .await
+            .unwrap()
+            .contains("<h1>404 Not Found</h1>"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
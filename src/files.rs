@@ -0,0 +1,634 @@
+use chrono::{DateTime, Utc};
+use flate2::Compression;
+use git2::{ObjectType, Oid};
+use rocket::fs::NamedFile;
+use rocket::http::uri::{fmt::Path as UriPath, Origin, Segments};
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::tokio;
+use rocket::Request;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::compress;
+use crate::config::{CacheConfig, SiteConfig, TrailingSlash, UrlConfig};
+use crate::gone;
+use crate::headers;
+use crate::ranges::{self, Partial};
+use crate::redirects;
+use crate::release::PerRelease;
+
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// bigger files are sent uncompressed rather than held in memory to
+/// compress them
+const MAX_ON_THE_FLY: u64 = 8 * 1024 * 1024;
+
+/// precompressed variants looked for next to a file, by preference
+const VARIANTS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// a site has many more files than releases
+const MAX_BLOB_IDS: usize = 100_000;
+
+/// git blob ids of released files
+#[derive(Clone, Default)]
+pub struct BlobIds(PerRelease<Option<Oid>, MAX_BLOB_IDS>);
+
+impl BlobIds {
+    /// the id git gives the file's content, which for anything that came
+    /// straight from the repository is its blob id in the deployed commit
+    async fn get(&self, path: &Path) -> Option<Oid> {
+        let (ids, file) = (self.0.clone(), path.to_path_buf());
+        let id = tokio::task::spawn_blocking(move || {
+            ids.get(&file, |x| Oid::hash_file(ObjectType::Blob, x).ok())
+        })
+        .await
+        .ok()?;
+        *id
+    }
+}
+
+enum Body {
+    File(NamedFile),
+    /// compressed for this request
+    Bytes(Vec<u8>),
+    /// an answer to a Range request
+    Partial(Partial),
+}
+
+/// a file from a release along with what clients need to cache it
+pub struct StaticFile {
+    body: Body,
+    /// of the file itself, whichever encoding it's sent in
+    content_type: Option<ContentType>,
+    encoding: Option<&'static str>,
+    /// whether the response depends on Accept-Encoding
+    negotiated: bool,
+    etag: Option<String>,
+    last_modified: Option<DateTime<Utc>>,
+    cache_control: Option<String>,
+}
+
+/// parses one of the site's own files like `_redirects`, giving back what
+/// it holds along with a problem for each line that can't be used
+pub type Parse<T> = fn(&str) -> (Vec<T>, Vec<String>);
+
+/// reads the site's file `name` from a served directory, a release
+/// without one just gets nothing from it
+pub fn read_site_file<T>(dir: &Path, name: &str, parse: Parse<T>) -> Vec<T> {
+    match fs::read_to_string(dir.join(name)) {
+        Ok(text) => parse(&text).0,
+        Err(_) => Vec::new(),
+    }
+}
+
+/// logs the lines of the site's file `name` that will be ignored, so they
+/// turn up with the deploy rather than once it's being served
+pub fn check_site_file<T>(dir: &Path, name: &str, parse: Parse<T>) {
+    if let Ok(text) = fs::read_to_string(dir.join(name)) {
+        for problem in parse(&text).1 {
+            warn!("ignoring {} {}", name, problem);
+        }
+    }
+}
+
+/// the path within the site that the request is for, `None` for ones that
+/// can never be served like dotfiles
+pub fn request_path(req: &Request<'_>) -> Option<PathBuf> {
+    req.segments::<Segments<'_, UriPath>>(0..)
+        .ok()?
+        .to_path_buf(false)
+        .ok()
+}
+
+/// the file at `path` under `root`, directories are served by their
+/// index.html and with clean urls `posts/hello` can be `posts/hello.html`,
+/// also when there's a `posts/hello/` without an index. `None` when
+/// there's nothing there or it's the site's own configuration
+pub fn locate(root: &Path, path: &Path, clean: bool) -> Option<PathBuf> {
+    if [redirects::FILE, headers::FILE, gone::FILE]
+        .iter()
+        .any(|x| path == Path::new(x))
+    {
+        return None;
+    }
+    let full = root.join(path);
+    let found = match full.is_dir() {
+        true => full.join("index.html"),
+        false => full.clone(),
+    };
+    if found.is_file() {
+        return Some(found);
+    }
+    if !clean || path.as_os_str().is_empty() {
+        return None;
+    }
+    let mut page = full.into_os_string();
+    page.push(".html");
+    let page = PathBuf::from(page);
+    page.is_file().then_some(page)
+}
+
+/// `locate` for a url path like the destination of a rewrite
+pub fn locate_url(root: &Path, url: &str, clean: bool) -> Option<PathBuf> {
+    let path = Origin::parse(url)
+        .ok()?
+        .path()
+        .segments()
+        .to_path_buf(false)
+        .ok()?;
+    locate(root, &path, clean)
+}
+
+/// the url a page found at `url` should be asked for by, when that isn't
+/// `url` already. only pages get their urls rewritten, other files are
+/// left where they are
+pub fn canonical(url: &str, located: &Path, urls: &UrlConfig) -> Option<String> {
+    if located.extension().is_none_or(|x| x != "html") {
+        return None;
+    }
+    let mut page = url.to_string();
+    if urls.clean {
+        if let Some(dir) = page.strip_suffix("index.html").filter(|x| x.ends_with('/')) {
+            page = dir.to_string();
+        } else if let Some(stem) = page.strip_suffix(".html") {
+            page = stem.to_string();
+        }
+    }
+    // a url naming the file itself can't end in a slash
+    if !page.ends_with(".html") {
+        match urls.trailing_slash {
+            TrailingSlash::Always if !page.ends_with('/') => page.push('/'),
+            TrailingSlash::Never if page.len() > 1 => {
+                page.truncate(page.trim_end_matches('/').len().max(1))
+            }
+            _ => {}
+        }
+    }
+    (page != url).then_some(page)
+}
+
+/// the path analytics counts a page under, the same however the link to
+/// it was written
+pub fn page_key(url: &str, urls: &UrlConfig) -> String {
+    let mut page = url;
+    if let Some(dir) = page.strip_suffix("index.html").filter(|x| x.ends_with('/')) {
+        page = dir;
+    } else if urls.clean {
+        page = page.strip_suffix(".html").unwrap_or(page);
+    }
+    match page.trim_end_matches('/') {
+        "" => "/".to_string(),
+        x => x.to_string(),
+    }
+}
+
+/// opens a file found by `locate` to answer the request. a precompressed
+/// `.br` or `.gz` next to it is sent instead when the client takes it,
+/// otherwise text is gzipped as it's sent. Range requests are answered
+/// from the file as it is
+pub async fn open(
+    req: &Request<'_>,
+    path: &Path,
+    site: &SiteConfig,
+    blob_ids: &BlobIds,
+) -> Option<StaticFile> {
+    // the live and preview links point into a release, it's the path in
+    // there that the blob id belongs to
+    let path = tokio::fs::canonicalize(path).await.ok()?;
+    let file = NamedFile::open(&path).await.ok()?;
+    let metadata = file.file().metadata().await.ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+
+    let compression = &site.compression;
+    let compressible = compress::compressible(&path);
+    // ranges of a compressed body would only make sense to a client that
+    // can decompress part of one
+    let range = req.headers().get_one("Range");
+    let mut body = Body::File(file);
+    let mut encoding = None;
+    // the response depends on Accept-Encoding as soon as there's a variant,
+    // even when this client can't take it
+    let mut has_variant = false;
+    for (coding, suffix) in VARIANTS {
+        let variant = compress::variant(&path, suffix);
+        if encoding.is_some() || range.is_some() || !compress::accepts(req, coding) {
+            has_variant |= tokio::fs::metadata(&variant)
+                .await
+                .is_ok_and(|x| x.is_file());
+        } else if let Ok(file) = NamedFile::open(&variant).await {
+            body = Body::File(file);
+            encoding = Some(coding);
+            has_variant = true;
+        }
+    }
+    let on_the_fly = encoding.is_none()
+        && range.is_none()
+        && compressible
+        && compression.on_the_fly
+        && (compression.min_size..=MAX_ON_THE_FLY).contains(&metadata.len())
+        && compress::accepts(req, "gzip");
+    if on_the_fly {
+        encoding = Some("gzip");
+    }
+
+    // each encoding is a different representation so it needs its own tag
+    let etag = blob_ids.get(&path).await.map(|x| match encoding {
+        Some(coding) => format!("\"{}-{}\"", x, coding),
+        None => format!("\"{}\"", x),
+    });
+    let mut file = StaticFile {
+        body,
+        content_type: path
+            .extension()
+            .and_then(|x| x.to_str())
+            .and_then(ContentType::from_extension),
+        encoding,
+        negotiated: compressible || has_variant,
+        etag,
+        last_modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+        cache_control: cache_control(&site.cache, &path),
+    };
+
+    // no point compressing or reading ranges for what won't be sent
+    if file.not_modified(req) {
+        return Some(file);
+    }
+    if on_the_fly {
+        let data = tokio::fs::read(&path).await.ok()?;
+        let compressed =
+            tokio::task::spawn_blocking(move || compress::gzip(&data, Compression::fast())).await;
+        match compressed {
+            Ok(Ok(x)) => file.body = Body::Bytes(x),
+            _ => return None,
+        }
+    }
+    let if_range = req
+        .headers()
+        .get_one("If-Range")
+        .is_none_or(|x| ranges::if_range(x, file.etag.as_deref(), file.last_modified));
+    if let Some(wanted) = range.and_then(|x| ranges::parse(x, metadata.len())) {
+        if if_range {
+            let partial = ranges::read(&path, metadata.len(), wanted, file.content_type.as_ref());
+            file.body = Body::Partial(partial.await.ok()?);
+        }
+    }
+    Some(file)
+}
+
+impl StaticFile {
+    /// whether the copy the client already has is still current. an
+    /// If-None-Match header means If-Modified-Since is ignored
+    fn not_modified(&self, req: &Request<'_>) -> bool {
+        if let Some(tags) = req.headers().get_one("If-None-Match") {
+            let etag = match &self.etag {
+                Some(x) => x,
+                None => return false,
+            };
+            // the weak comparison is the one to use for If-None-Match
+            return tags.trim() == "*"
+                || tags
+                    .split(',')
+                    .any(|x| x.trim().trim_start_matches("W/") == etag);
+        }
+        let since = req
+            .headers()
+            .get_one("If-Modified-Since")
+            .and_then(|x| DateTime::parse_from_rfc2822(x).ok());
+        match (since, self.last_modified) {
+            // http dates don't go below seconds
+            (Some(since), Some(modified)) => modified.timestamp() <= since.timestamp(),
+            _ => false,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for StaticFile {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let not_modified = self.not_modified(req);
+        let mut content_type = self.content_type;
+        let mut response = match (not_modified, self.body) {
+            (true, _) => Response::build().status(Status::NotModified).finalize(),
+            (false, Body::File(file)) => file.respond_to(req)?,
+            (false, Body::Bytes(bytes)) => Response::build()
+                .sized_body(bytes.len(), Cursor::new(bytes))
+                .finalize(),
+            (false, Body::Partial(Partial::Single { range, total, body })) => Response::build()
+                .status(Status::PartialContent)
+                .raw_header(
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", range.start, range.end, total),
+                )
+                .raw_header("Content-Length", range.len().to_string())
+                .streamed_body(body)
+                .finalize(),
+            (
+                false,
+                Body::Partial(Partial::Multiple {
+                    boundary,
+                    length,
+                    body,
+                }),
+            ) => {
+                content_type = ContentType::parse_flexible(&format!(
+                    "multipart/byteranges; boundary={}",
+                    boundary
+                ));
+                Response::build()
+                    .status(Status::PartialContent)
+                    .raw_header("Content-Length", length.to_string())
+                    .streamed_body(body)
+                    .finalize()
+            }
+            (false, Body::Partial(Partial::Unsatisfiable { total })) => {
+                content_type = None;
+                Response::build()
+                    .status(Status::RangeNotSatisfiable)
+                    .raw_header("Content-Range", format!("bytes */{}", total))
+                    .finalize()
+            }
+        };
+        if !not_modified {
+            // a precompressed variant would otherwise go out as application/gzip,
+            // or the original's type when it has none
+            match content_type {
+                Some(content_type) => {
+                    response.set_header(content_type);
+                }
+                None if self.encoding.is_some() => response.remove_header("Content-Type"),
+                None => {}
+            }
+            match self.encoding {
+                Some(encoding) => response.set_header(Header::new("Content-Encoding", encoding)),
+                None => response.set_header(Header::new("Accept-Ranges", "bytes")),
+            };
+        }
+        if self.negotiated {
+            response.set_header(Header::new("Vary", "Accept-Encoding"));
+        }
+        if let Some(etag) = self.etag {
+            response.set_header(Header::new("ETag", etag));
+        }
+        if let Some(modified) = self.last_modified {
+            response.set_header(Header::new(
+                "Last-Modified",
+                modified.format(HTTP_DATE).to_string(),
+            ));
+        }
+        if let Some(cache_control) = self.cache_control {
+            response.set_header(Header::new("Cache-Control", cache_control));
+        }
+        Ok(response)
+    }
+}
+
+/// the Cache-Control header configured for the file at `path`
+fn cache_control(cache: &CacheConfig, path: &Path) -> Option<String> {
+    if is_fingerprinted(path) {
+        if let Some(x) = &cache.fingerprinted {
+            return Some(x.clone());
+        }
+    }
+    let extension = path
+        .extension()
+        .map(|x| x.to_string_lossy().to_ascii_lowercase());
+    extension
+        .and_then(|x| cache.extensions.get(&x))
+        .or_else(|| cache.extensions.get("*"))
+        .cloned()
+}
+
+/// names like `app.3f9a2c1b.js` carry a hash of the content, so a file by
+/// that name never changes. the hash has to be a part of its own after
+/// the name, and pages are never treated this way since their urls don't
+/// change when they're edited
+fn is_fingerprinted(path: &Path) -> bool {
+    let page = path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("html") || x.eq_ignore_ascii_case("htm"));
+    let stem = match path.file_stem().and_then(|x| x.to_str()) {
+        Some(x) if !page => x,
+        _ => return false,
+    };
+    let mut parts = stem.split(['.', '-']);
+    let name = parts.next().unwrap_or_default();
+    !name.is_empty()
+        && parts.any(|part| {
+            part.len() >= 8
+                && part.bytes().all(|c| c.is_ascii_hexdigit())
+                && part.bytes().any(|c| c.is_ascii_digit())
+                && part.bytes().any(|c| c.is_ascii_alphabetic())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_sites;
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::figment::Figment;
+    use rocket::local::asynchronous::Client;
+
+    #[test]
+    fn fingerprints_need_a_name_and_a_hash_of_their_own() {
+        assert!(is_fingerprinted(Path::new("app.3f9a2c1b.js")));
+        assert!(is_fingerprinted(Path::new("assets/main-3f9a2c1b.css")));
+        assert!(is_fingerprinted(Path::new("chunk.3f9a2c1b.min.js")));
+        assert!(!is_fingerprinted(Path::new("facade12.css")));
+        assert!(!is_fingerprinted(Path::new("3f9a2c1b.js")));
+        assert!(!is_fingerprinted(Path::new("app.deadbeef.js")));
+        assert!(!is_fingerprinted(Path::new("app.12345678.js")));
+    }
+
+    #[test]
+    fn locates_pages_and_indexes() {
+        let root = std::env::temp_dir().join(format!("bloghoster-files-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::create_dir_all(root.join("posts/images")).unwrap();
+        for file in [
+            "index.html",
+            "about.html",
+            "notes.txt",
+            "_redirects",
+            "docs/index.html",
+            "posts.html",
+        ] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        let found = |path: &str, clean| {
+            locate(&root, Path::new(path), clean).map(|x| {
+                x.strip_prefix(&root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+        };
+
+        assert_eq!(found("", true).as_deref(), Some("index.html"));
+        assert_eq!(found("docs", true).as_deref(), Some("docs/index.html"));
+        assert_eq!(found("docs", false).as_deref(), Some("docs/index.html"));
+        assert_eq!(found("about.html", false).as_deref(), Some("about.html"));
+        assert_eq!(found("about", true).as_deref(), Some("about.html"));
+        assert_eq!(found("about", false), None);
+        assert_eq!(found("notes.txt", true).as_deref(), Some("notes.txt"));
+        // a directory without an index doesn't hide the page next to it
+        assert_eq!(found("posts", true).as_deref(), Some("posts.html"));
+        assert_eq!(found("posts", false), None);
+        assert_eq!(found("posts/images", true), None);
+        assert_eq!(found("missing", true), None);
+        assert_eq!(found("_redirects", true), None);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    fn urls(clean: bool, trailing_slash: TrailingSlash) -> UrlConfig {
+        UrlConfig {
+            clean,
+            trailing_slash,
+        }
+    }
+
+    #[test]
+    fn canonical_urls_of_pages() {
+        let page = Path::new("/srv/posts/hello.html");
+        let index = Path::new("/srv/docs/index.html");
+        let clean = urls(true, TrailingSlash::Preserve);
+        assert_eq!(
+            canonical("/posts/hello.html", page, &clean).as_deref(),
+            Some("/posts/hello")
+        );
+        assert_eq!(canonical("/posts/hello", page, &clean), None);
+        assert_eq!(
+            canonical("/docs/index.html", index, &clean).as_deref(),
+            Some("/docs/")
+        );
+        assert_eq!(canonical("/docs", index, &clean), None);
+        assert_eq!(
+            canonical("/notes.txt", Path::new("/srv/notes.txt"), &clean),
+            None
+        );
+
+        let plain = urls(false, TrailingSlash::Preserve);
+        assert_eq!(canonical("/posts/hello.html", page, &plain), None);
+        assert_eq!(canonical("/docs/index.html", index, &plain), None);
+    }
+
+    #[test]
+    fn canonical_trailing_slashes() {
+        let index = Path::new("/srv/docs/index.html");
+        let always = urls(true, TrailingSlash::Always);
+        assert_eq!(
+            canonical("/docs", index, &always).as_deref(),
+            Some("/docs/")
+        );
+        assert_eq!(canonical("/docs/", index, &always), None);
+        let never = urls(true, TrailingSlash::Never);
+        assert_eq!(canonical("/docs/", index, &never).as_deref(), Some("/docs"));
+        assert_eq!(canonical("/", Path::new("/srv/index.html"), &never), None);
+        // the file's own name never gets a slash
+        let always = urls(false, TrailingSlash::Always);
+        assert_eq!(canonical("/docs/index.html", index, &always), None);
+    }
+
+    #[test]
+    fn page_keys_ignore_how_the_link_was_written() {
+        let clean = urls(true, TrailingSlash::Preserve);
+        for url in ["/posts/hello", "/posts/hello/", "/posts/hello.html"] {
+            assert_eq!(page_key(url, &clean), "/posts/hello");
+        }
+        for url in ["/", "/index.html"] {
+            assert_eq!(page_key(url, &clean), "/");
+        }
+        assert_eq!(page_key("/docs/index.html", &clean), "/docs");
+
+        let plain = urls(false, TrailingSlash::Preserve);
+        assert_eq!(page_key("/posts/hello.html", &plain), "/posts/hello.html");
+        assert_eq!(page_key("/docs/index.html", &plain), "/docs");
+    }
+
+    struct Served {
+        encoding: Option<&'static str>,
+        content_type: Option<String>,
+        vary: bool,
+    }
+
+    async fn serve(root: &Path, name: &str, accept: Option<&str>) -> Served {
+        let toml = format!("[site]\nrepo = \"x\"\nlive_dir = \"{}\"\n", root.display());
+        let site = load_sites(&Figment::from(Toml::string(&toml)))
+            .unwrap()
+            .remove(0);
+        let client = Client::untracked(rocket::build()).await.unwrap();
+        let mut req = client.get("/");
+        if let Some(accept) = accept {
+            req.add_header(Header::new("Accept-Encoding", accept.to_string()));
+        }
+        let file = open(&req, &root.join(name), &site, &BlobIds::default())
+            .await
+            .unwrap();
+        let encoding = file.encoding;
+        let response = file.respond_to(&req).unwrap();
+        Served {
+            encoding,
+            content_type: response.content_type().map(|x| x.to_string()),
+            vary: response.headers().contains("Vary"),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn picks_precompressed_variants() {
+        let root = std::env::temp_dir().join(format!("bloghoster-variants-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        for file in [
+            "page.html",
+            "page.html.br",
+            "page.html.gz",
+            "data.zzz",
+            "data.zzz.gz",
+            "photo.png",
+        ] {
+            std::fs::write(root.join(file), "x").unwrap();
+        }
+
+        let served = serve(&root, "page.html", Some("gzip, br")).await;
+        assert_eq!(served.encoding, Some("br"));
+        assert_eq!(
+            served.content_type.as_deref(),
+            Some("text/html; charset=utf-8")
+        );
+        assert!(served.vary);
+        let served = serve(&root, "page.html", Some("br;q=0, gzip")).await;
+        assert_eq!(served.encoding, Some("gzip"));
+        assert_eq!(
+            served.content_type.as_deref(),
+            Some("text/html; charset=utf-8")
+        );
+        let served = serve(&root, "page.html", None).await;
+        assert_eq!(served.encoding, None);
+
+        // the variant of a file of unknown type doesn't make it a gzip file
+        let served = serve(&root, "data.zzz", Some("gzip")).await;
+        assert_eq!(served.encoding, Some("gzip"));
+        assert_eq!(served.content_type, None);
+        assert!(served.vary);
+        // and caches still have to know another client could get it
+        let served = serve(&root, "data.zzz", Some("identity")).await;
+        assert_eq!(served.encoding, None);
+        assert!(served.vary);
+
+        let served = serve(&root, "photo.png", Some("gzip")).await;
+        assert_eq!(served.encoding, None);
+        assert!(!served.vary);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn pages_are_never_fingerprinted() {
+        assert!(!is_fingerprinted(Path::new("posts/decade2020.html")));
+        assert!(!is_fingerprinted(Path::new("posts/hello.3f9a2c1b.html")));
+        assert!(!is_fingerprinted(Path::new("old/index.3f9a2c1b.HTM")));
+    }
+}
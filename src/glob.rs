@@ -0,0 +1,73 @@
+/// whether `text` matches `pattern`, `*` matches any run of characters and
+/// `?` any single one
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // where to pick up again if what follows the last `*` stops matching
+    let mut retry = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                retry = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match retry {
+                Some((star, from)) => {
+                    p = star + 1;
+                    t = from + 1;
+                    retry = Some((star, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_literally_without_wildcards() {
+        assert!(matches("/about", "/about"));
+        assert!(!matches("/about", "/about/"));
+        assert!(!matches("/about", "/abou"));
+        assert!(matches("", ""));
+        assert!(!matches("", "/"));
+    }
+
+    #[test]
+    fn stars_match_any_run() {
+        assert!(matches("*.html", "/posts/hello.html"));
+        assert!(matches("*.html", ".html"));
+        assert!(!matches("*.html", "/posts/hello.htm"));
+
+        assert!(matches("/assets/*", "/assets/"));
+        assert!(matches("/assets/*", "/assets/css/site.css"));
+        assert!(!matches("/assets/*", "/assets"));
+
+        assert!(matches(
+            "/posts/*/index.html",
+            "/posts/2024/hello/index.html"
+        ));
+        assert!(matches("/a*b*c", "/aXbYbZc"));
+        assert!(!matches("/a*b*c", "/aXbYcZ"));
+        assert!(matches("*", ""));
+        assert!(matches("**", "anything"));
+    }
+
+    #[test]
+    fn question_marks_match_one_character() {
+        assert!(matches("v?.?", "v1.2"));
+        assert!(!matches("v?.?", "v1.23"));
+        assert!(matches("drafts/?*", "drafts/x"));
+        assert!(!matches("drafts/?*", "drafts/"));
+    }
+}
@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use crate::files;
+use crate::glob;
+
+/// the file in the served directory listing what was taken down
+pub const FILE: &str = "_gone";
+
+/// parses a `_gone` file, one path per line where `*` matches anything.
+/// lines that can't be used are returned as problems
+fn parse(text: &str) -> (Vec<String>, Vec<String>) {
+    let mut patterns = Vec::new();
+    let mut problems = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.starts_with('/') && !line.contains(char::is_whitespace) {
+            true => patterns.push(line.to_string()),
+            false => problems.push(format!(
+                "line {}: expected a path starting with `/`",
+                number + 1
+            )),
+        }
+    }
+    (patterns, problems)
+}
+
+/// whether `path` was listed as gone, a trailing slash makes no difference
+pub fn is_gone(patterns: &[String], path: &str) -> bool {
+    let trimmed = match path.trim_end_matches('/') {
+        "" => "/",
+        x => x,
+    };
+    patterns
+        .iter()
+        .any(|x| glob::matches(x, path) || glob::matches(x.trim_end_matches('/'), trimmed))
+}
+
+/// the listed paths of a release, for `PerRelease`
+pub fn load(dir: &Path) -> Vec<String> {
+    files::read_site_file(dir, FILE, parse)
+}
+
+pub fn check(dir: &Path) {
+    files::check_site_file(dir, FILE, parse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(text: &str) -> Vec<String> {
+        let (patterns, problems) = parse(text);
+        assert!(problems.is_empty(), "{:?}", problems);
+        patterns
+    }
+
+    #[test]
+    fn reports_lines_it_cant_use() {
+        let (patterns, problems) = parse("# comment\n\n/old\nold\n/two words\n  /indented\n");
+        assert_eq!(patterns, ["/old", "/indented"]);
+        let lines: Vec<_> = problems
+            .iter()
+            .map(|x| x.split(':').next().unwrap())
+            .collect();
+        assert_eq!(lines, ["line 4", "line 5"]);
+    }
+
+    #[test]
+    fn ignores_trailing_slashes() {
+        let patterns = patterns("/old\n/posts/removed/\n");
+        assert!(is_gone(&patterns, "/old"));
+        assert!(is_gone(&patterns, "/old/"));
+        assert!(is_gone(&patterns, "/posts/removed"));
+        assert!(is_gone(&patterns, "/posts/removed/"));
+        assert!(!is_gone(&patterns, "/older"));
+        assert!(!is_gone(&patterns, "/old/more"));
+        assert!(!is_gone(&patterns, "/"));
+    }
+
+    #[test]
+    fn matches_the_root_and_wildcards() {
+        assert!(is_gone(&patterns("/\n"), "/"));
+        assert!(!is_gone(&patterns("/\n"), "/old"));
+
+        let everything = patterns("/*\n");
+        assert!(is_gone(&everything, "/"));
+        assert!(is_gone(&everything, "/old"));
+        assert!(is_gone(&everything, "/posts/old/"));
+
+        let drafts = patterns("/drafts/*\n");
+        assert!(is_gone(&drafts, "/drafts/one.html"));
+        assert!(is_gone(&drafts, "/drafts/"));
+        assert!(!is_gone(&drafts, "/posts/one.html"));
+    }
+}
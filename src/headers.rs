@@ -0,0 +1,234 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+use std::path::Path;
+
+use crate::files;
+use crate::glob;
+use crate::release::PerRelease;
+use crate::sites::{Sites, SITE_FILES};
+
+/// the file in the served directory the headers are read from
+pub const FILE: &str = "_headers";
+
+/// a path pattern from `_headers` and the headers indented under it
+#[derive(Debug)]
+pub struct Block {
+    pattern: String,
+    headers: Vec<(String, String)>,
+}
+
+/// parses a `_headers` file. each unindented line is a path pattern where
+/// `*` matches anything, and the indented `Name: value` lines after it are
+/// the headers for paths that match. lines that can't be used are
+/// returned as problems
+fn parse(text: &str) -> (Vec<Block>, Vec<String>) {
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut problems = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let problem = if !line.starts_with(char::is_whitespace) {
+            match trimmed.starts_with('/') {
+                true => {
+                    blocks.push(Block {
+                        pattern: trimmed.to_string(),
+                        headers: Vec::new(),
+                    });
+                    continue;
+                }
+                false => "expected a path starting with `/`",
+            }
+        } else {
+            match (blocks.last_mut(), trimmed.split_once(':')) {
+                (None, _) => "a header has to come after a path",
+                (Some(block), Some((name, value))) if is_token(name.trim()) => {
+                    block
+                        .headers
+                        .push((name.trim().to_string(), value.trim().to_string()));
+                    continue;
+                }
+                _ => "expected `Name: value`",
+            }
+        };
+        problems.push(format!("line {}: {}", number + 1, problem));
+    }
+    (blocks, problems)
+}
+
+/// whether `name` can be used as a header name
+fn is_token(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c))
+}
+
+/// the header blocks of a release, for `PerRelease`
+pub fn load(dir: &Path) -> Vec<Block> {
+    files::read_site_file(dir, FILE, parse)
+}
+
+pub fn check(dir: &Path) {
+    files::check_site_file(dir, FILE, parse)
+}
+
+/// the headers the blocks matching `path` add up to. a later block setting
+/// the same header overrides an earlier one, repeating it within a block
+/// sends it more than once
+fn for_path<'a>(blocks: &'a [Block], path: &str) -> Vec<(&'a str, Vec<&'a str>)> {
+    let mut headers: Vec<(&str, Vec<&str>)> = Vec::new();
+    for block in blocks.iter().filter(|x| glob::matches(&x.pattern, path)) {
+        let mut replaced = Vec::new();
+        for (name, value) in &block.headers {
+            let i = match headers.iter().position(|x| x.0.eq_ignore_ascii_case(name)) {
+                Some(i) => i,
+                None => {
+                    headers.push((name, Vec::new()));
+                    headers.len() - 1
+                }
+            };
+            if !replaced.contains(&i) {
+                headers[i].1.clear();
+                replaced.push(i);
+            }
+            headers[i].1.push(value);
+        }
+    }
+    headers
+}
+
+/// adds the headers from the `_headers` file of the release being served
+/// to its files and error pages, replacing any the server set itself
+#[derive(Default)]
+pub struct CustomHeaders {
+    blocks: PerRelease<Vec<Block>>,
+}
+
+#[rocket::async_trait]
+impl Fairing for CustomHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Custom Headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        // the server's own pages like /admin aren't part of the site
+        if req
+            .route()
+            .is_some_and(|x| x.name.as_deref() != Some(SITE_FILES))
+        {
+            return;
+        }
+        let root = match req
+            .rocket()
+            .state::<Sites>()
+            .and_then(|x| x.served_path(req))
+        {
+            Some(x) => x,
+            None => return,
+        };
+        let blocks = self.blocks.get(&root, load);
+        for (name, values) in for_path(&blocks, req.uri().path().as_str()) {
+            res.remove_header(name);
+            for value in values {
+                res.adjoin_header(Header::new(name.to_string(), value.to_string()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(text: &str) -> Vec<Block> {
+        let (blocks, problems) = parse(text);
+        assert!(problems.is_empty(), "{:?}", problems);
+        blocks
+    }
+
+    #[test]
+    fn parses_indented_headers_under_paths() {
+        let blocks = blocks(
+            "# comment\n/*\n  X-Frame-Options: DENY\n\n/assets/*\n\tCache-Control:  max-age=31536000 \n",
+        );
+        let parsed: Vec<_> = blocks
+            .iter()
+            .map(|x| (x.pattern.as_str(), x.headers.clone()))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                ("/*", vec![("X-Frame-Options".into(), "DENY".into())]),
+                (
+                    "/assets/*",
+                    vec![("Cache-Control".into(), "max-age=31536000".into())]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_lines_it_cant_use() {
+        let (blocks, problems) = parse(
+            "  X-Early: 1\nnot-a-path\n/ok\n  X-Fine: yes\n  no colon\n  Bad Name: x\n  : empty\nX-Unindented: 1\n",
+        );
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].headers.len(), 1);
+        let lines: Vec<_> = problems
+            .iter()
+            .map(|x| x.split(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            ["line 1", "line 2", "line 5", "line 6", "line 7", "line 8"]
+        );
+    }
+
+    #[test]
+    fn checks_header_names() {
+        assert!(is_token("X-Frame-Options"));
+        assert!(is_token("x_custom.1~"));
+        assert!(!is_token(""));
+        assert!(!is_token("Bad Name"));
+        assert!(!is_token("Bad(Name)"));
+        assert!(!is_token("Ünicode"));
+    }
+
+    #[test]
+    fn later_blocks_replace_headers() {
+        let blocks = blocks(
+            "/*\n  Cache-Control: no-cache\n  X-Frame-Options: DENY\n/assets/*\n  cache-control: max-age=60\n",
+        );
+        assert_eq!(
+            for_path(&blocks, "/assets/app.js"),
+            [
+                ("Cache-Control", vec!["max-age=60"]),
+                ("X-Frame-Options", vec!["DENY"])
+            ]
+        );
+        assert_eq!(
+            for_path(&blocks, "/index.html"),
+            [
+                ("Cache-Control", vec!["no-cache"]),
+                ("X-Frame-Options", vec!["DENY"])
+            ]
+        );
+    }
+
+    #[test]
+    fn repeats_within_a_block_add_up() {
+        let blocks = blocks("/*\n  Link: </a.css>\n/page\n  Link: </b.css>\n  Link: </c.js>\n");
+        assert_eq!(
+            for_path(&blocks, "/page"),
+            [("Link", vec!["</b.css>", "</c.js>"])]
+        );
+        assert_eq!(for_path(&blocks, "/other"), [("Link", vec!["</a.css>"])]);
+        assert!(for_path(&blocks[1..], "/other").is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+use git2::{Commit, ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+use rocket::serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::auth;
+use crate::config::{LfsConfig, SiteConfig};
+use crate::deploy::DeployError;
+use crate::pull;
+use crate::signing;
+
+const POINTER_VERSION: &[u8] = b"version https://git-lfs.github.com/spec/v1";
+
+/// pointer files are tiny, anything bigger is real content
+const MAX_POINTER: usize = 1024;
+
+/// a file in the tree that stands in for an LFS object
+struct Pointer {
+    path: PathBuf,
+    oid: String,
+    size: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct BatchResponse {
+    objects: Vec<BatchObject>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct BatchObject {
+    oid: String,
+    size: u64,
+    actions: Option<BatchActions>,
+    error: Option<BatchError>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct BatchActions {
+    download: Option<BatchAction>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct BatchAction {
+    href: String,
+    #[serde(default)]
+    header: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct BatchError {
+    message: String,
+}
+
+/// swaps the LFS pointer files exported from `commit` into `dir` for the
+/// objects they point at. objects are kept under the repository's
+/// `lfs/objects` like git-lfs does so each is only downloaded once.
+/// submodules aren't looked in, their objects live on their own servers
+pub fn resolve(
+    site: &SiteConfig,
+    repo: &Repository,
+    commit: &Commit,
+    dir: &Path,
+) -> Result<(), DeployError> {
+    let mut pointers = pointers(repo, commit).map_err(DeployError::Checkout)?;
+    pointers.retain(|x| pull::checks_out(&site.sparse_paths, &x.path));
+    if pointers.is_empty() {
+        return Ok(());
+    }
+    let lfs = match &site.lfs {
+        Some(x) => x,
+        None => {
+            warn!(
+                "serving {} Git LFS pointer files as they are, lfs isn't configured",
+                pointers.len()
+            );
+            return Ok(());
+        }
+    };
+
+    let cache = repo.path().join("lfs").join("objects");
+    let mut missing = Vec::new();
+    for pointer in &pointers {
+        if object_path(&cache, &pointer.oid).is_file() {
+            continue;
+        }
+        let stored = lfs.store.as_ref().map(|x| object_path(x, &pointer.oid));
+        match stored.map(fs::File::open) {
+            Some(Ok(file)) => store(&cache, &pointer.oid, pointer.size, file)
+                .map_err(|e| DeployError::Lfs(format!("{}: {}", pointer.oid, e)))?,
+            _ => missing.push(pointer),
+        }
+    }
+    if !missing.is_empty() {
+        info!("downloading {} Git LFS objects", missing.len());
+        download(site, lfs, &missing, &cache).map_err(DeployError::Lfs)?;
+    }
+
+    for pointer in &pointers {
+        fs::copy(object_path(&cache, &pointer.oid), dir.join(&pointer.path))
+            .map_err(DeployError::Release)?;
+    }
+    Ok(())
+}
+
+/// reads the oid and size out of a pointer file
+fn parse(content: &[u8]) -> Option<(String, u64)> {
+    if !content.starts_with(POINTER_VERSION) {
+        return None;
+    }
+    let (mut oid, mut size) = (None, None);
+    for line in std::str::from_utf8(content).ok()?.lines() {
+        if let Some(x) = line.strip_prefix("oid sha256:") {
+            oid = Some(x.trim().to_ascii_lowercase());
+        } else if let Some(x) = line.strip_prefix("size ") {
+            size = x.trim().parse().ok();
+        }
+    }
+    let oid = oid.filter(|x| x.len() == 64 && x.bytes().all(|c| c.is_ascii_hexdigit()))?;
+    Some((oid, size?))
+}
+
+fn pointers(repo: &Repository, commit: &Commit) -> Result<Vec<Pointer>, git2::Error> {
+    let odb = repo.odb()?;
+    let mut found = Vec::new();
+    let mut failed = None;
+    commit.tree()?.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() != Some(ObjectType::Blob) {
+            return TreeWalkResult::Ok;
+        }
+        // the header is enough to skip everything that's too big to be one
+        let small = odb.read_header(entry.id()).map(|x| x.0 <= MAX_POINTER);
+        let content = match small {
+            Ok(true) => odb.read(entry.id()).map(|x| parse(x.data())),
+            Ok(false) => return TreeWalkResult::Ok,
+            Err(e) => Err(e),
+        };
+        match content {
+            Ok(Some((oid, size))) => found.push(Pointer {
+                path: Path::new(root).join(String::from_utf8_lossy(entry.name_bytes()).as_ref()),
+                oid,
+                size,
+            }),
+            Ok(None) => {}
+            Err(e) => {
+                failed = Some(e);
+                return TreeWalkResult::Abort;
+            }
+        }
+        TreeWalkResult::Ok
+    })?;
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(found),
+    }
+}
+
+/// where an object lives in a git-lfs style object directory
+fn object_path(objects: &Path, oid: &str) -> PathBuf {
+    objects.join(&oid[..2]).join(&oid[2..4]).join(oid)
+}
+
+/// copies an object into the cache, refusing it unless it hashes to `oid`
+fn store(cache: &Path, oid: &str, size: u64, mut object: impl Read) -> io::Result<()> {
+    let path = object_path(cache, oid);
+    let partial = path.with_extension("partial");
+    fs::create_dir_all(path.parent().unwrap_or(cache))?;
+
+    let mut file = fs::File::create(&partial)?;
+    let mut hasher = Sha256::new();
+    let mut written = 0;
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let n = object.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        file.write_all(&buf[..n])?;
+        written += n as u64;
+    }
+
+    let hash = signing::hex(&hasher.finalize());
+    if hash != oid || written != size {
+        let _ = fs::remove_file(&partial);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "object doesn't match its pointer",
+        ));
+    }
+    fs::rename(partial, path)
+}
+
+/// the LFS server for the site, either configured or worked out from the
+/// remote the same way git-lfs does
+fn endpoint(site: &SiteConfig, lfs: &LfsConfig) -> Option<String> {
+    if let Some(endpoint) = &lfs.endpoint {
+        return Some(endpoint.trim_end_matches('/').to_string());
+    }
+    let repo = site.repo.trim_end_matches('/');
+    let url = if repo.starts_with("https://") || repo.starts_with("http://") {
+        repo.to_string()
+    } else if let Some(rest) = repo.strip_prefix("ssh://") {
+        // the ssh port has nothing to do with the https one
+        let (host, path) = rest.split_once('/')?;
+        let host = host.rsplit('@').next()?.split(':').next()?;
+        format!("https://{}/{}", host, path)
+    } else {
+        let (host, path) = repo.split_once(':')?;
+        let host = host.rsplit('@').next()?;
+        if host.contains('/') {
+            return None;
+        }
+        format!("https://{}/{}", host, path)
+    };
+    let url = match url.ends_with(".git") {
+        true => url,
+        false => format!("{}.git", url),
+    };
+    Some(format!("{}/info/lfs", url))
+}
+
+/// fetches objects through the LFS batch api and its basic transfer
+fn download(
+    site: &SiteConfig,
+    lfs: &LfsConfig,
+    pointers: &[&Pointer],
+    cache: &Path,
+) -> Result<(), String> {
+    let endpoint =
+        endpoint(site, lfs).ok_or_else(|| format!("no LFS endpoint is known for {}", site.repo))?;
+    let objects: Vec<_> = pointers
+        .iter()
+        .map(|x| serde_json::json!({ "oid": x.oid, "size": x.size }))
+        .collect();
+
+    let mut request = ureq::post(&format!("{}/objects/batch", endpoint))
+        .set("Accept", "application/vnd.git-lfs+json")
+        .set("Content-Type", "application/vnd.git-lfs+json");
+    if let Some(authorization) = auth::http_authorization(&site.credentials) {
+        request = request.set("Authorization", &authorization);
+    }
+    let batch: BatchResponse = request
+        .send_json(serde_json::json!({
+            "operation": "download",
+            "transfers": ["basic"],
+            "objects": objects,
+        }))
+        .map_err(|e| format!("LFS batch request failed: {}", e))?
+        .into_json()
+        .map_err(|e| format!("bad LFS batch response: {}", e))?;
+
+    for object in batch.objects {
+        // the oid ends up in a path, only take the ones we asked for
+        if !pointers.iter().any(|x| x.oid == object.oid) {
+            continue;
+        }
+        if let Some(error) = object.error {
+            return Err(format!("{}: {}", object.oid, error.message));
+        }
+        let action = match object.actions.and_then(|x| x.download) {
+            Some(x) => x,
+            None => return Err(format!("{}: no download offered", object.oid)),
+        };
+        let mut request = ureq::get(&action.href);
+        for (name, value) in &action.header {
+            request = request.set(name, value);
+        }
+        let response = request
+            .call()
+            .map_err(|e| format!("{}: download failed: {}", object.oid, e))?;
+        store(cache, &object.oid, object.size, response.into_reader())
+            .map_err(|e| format!("{}: {}", object.oid, e))?;
+    }
+    Ok(())
+}
@@ -1,54 +1,92 @@
 #[macro_use]
 extern crate rocket;
 
-use git2::Repository;
 // use rocket_analytics::Analytics;
 
+mod admin;
 mod analytics;
+mod auth;
+mod build;
+mod compress;
+mod config;
+mod deploy;
+mod errors;
+mod files;
+mod glob;
+mod gone;
+mod headers;
+mod lfs;
+mod notify;
+mod poll;
+mod preview;
 mod pull;
+mod ranges;
+mod redirects;
+mod release;
+mod signing;
+mod sites;
+mod submodules;
+mod tags;
+mod verify;
+mod webhook;
 
 use analytics::Db;
+use deploy::{DeployStatus, DeployTicket, Deployer, Trigger};
+use sites::{Site, SiteFiles, Sites};
+use webhook::Push;
 
 use rocket::{
     // fairing::{self, AdHoc}, fs::{relative, FileServer, NamedFile}, http::hyper::request, Build, Request, Rocket
     fairing::{self, AdHoc},
-    fs::{relative, FileServer, NamedFile},
+    response::status,
+    serde::json::Json,
     Build,
     Rocket,
 };
 use rocket_db_pools::Database;
 use rocket_dyn_templates::Template;
 // use std::path::{Path, PathBuf};
-use std::path::Path;
 
-fn git_refresh() {
-    let url = "https://github.com/uberfig/ivytime.gay.git";
-
-    let repo = match Repository::open("./static") {
-        Ok(repo) => repo,
-        Err(_e) => match Repository::clone(url, "./static") {
-            Ok(repo) => repo,
-            Err(e) => panic!("failed to clone: {}", e),
-        },
-    };
-
-    //git pull
-    let remote_branch = "main";
-    let mut remote = repo.find_remote("origin").unwrap();
-    let fetch_commit = pull::do_fetch(&repo, &[remote_branch], &mut remote).unwrap();
-    let _x = pull::do_merge(&repo, &remote_branch, fetch_commit);
+#[derive(Responder)]
+enum RefreshResponse {
+    Accepted(status::Accepted<Json<DeployTicket>>),
+    /// previews aren't tracked in the deploy history so there's no ticket
+    Preview(status::Accepted<()>),
+    Ignored(status::NoContent),
 }
 
-#[post("/")]
-fn refresh() {
-    git_refresh();
+#[post("/", data = "<push>")]
+fn refresh(site: &Site, push: Push) -> RefreshResponse {
+    info!(
+        "{:?} push to {:?} for {}",
+        push.provider, push.git_ref, site.config.name
+    );
+    if let Some(pattern) = &site.config.tags {
+        // the worker picks the newest matching tag, not necessarily this one
+        if push.tag().is_some_and(|x| glob::matches(pattern, x)) {
+            let ticket = site.deployer.request(Trigger::Webhook);
+            return RefreshResponse::Accepted(status::Accepted(Json(ticket)));
+        }
+    }
+    let branch = match push.branch() {
+        Some(x) => x,
+        None => return RefreshResponse::Ignored(status::NoContent),
+    };
+    if branch == site.config.branch && site.config.tags.is_none() {
+        let ticket = site.deployer.request(Trigger::Webhook);
+        return RefreshResponse::Accepted(status::Accepted(Json(ticket)));
+    }
+    // pushes that delete a branch land here too, the sync removes its preview
+    if preview::wants(&site.config, branch) {
+        site.deployer.retry_previews();
+        return RefreshResponse::Preview(status::Accepted(()));
+    }
+    RefreshResponse::Ignored(status::NoContent)
 }
 
-#[catch(404)]
-async fn not_found() -> Option<NamedFile> {
-    let path = Path::new(relative!("static/public/404.html"));
-
-    NamedFile::open(path).await.ok()
+#[get("/status")]
+fn refresh_status(site: &Site) -> Json<Option<DeployStatus>> {
+    Json(site.deployer.last_outcome().map(DeployStatus::from))
 }
 
 async fn run_migrations(rocket: Rocket<Build>) -> fairing::Result {
@@ -74,14 +112,24 @@ pub fn stage() -> AdHoc {
 
 #[launch]
 fn rocket() -> _ {
-    git_refresh();
+    let figment = rocket::Config::figment().merge(("port", 8080));
+    let sites = match config::load_sites(&figment) {
+        Ok(sites) => sites,
+        Err(e) => panic!("invalid site configuration: {}", e),
+    };
+
     rocket::build()
-        .configure(rocket::Config::figment().merge(("port", 8080)))
+        .configure(figment)
         .attach(Template::fairing())
         .attach(stage())
-        .mount("/", FileServer::from(relative!("static/public")))
+        .mount("/", SiteFiles::default())
         .mount("/analytics", analytics::routes())
-        .mount("/refresh", routes![refresh])
+        .mount("/admin", admin::routes())
+        .mount("/refresh", routes![refresh, refresh_status])
         .attach(analytics::Analytics::new())
-        .register("/", catchers![not_found])
+        .attach(headers::CustomHeaders::default())
+        .register("/", errors::catchers())
+        .register("/admin", admin::catchers())
+        .attach(Deployer::fairing())
+        .manage(Sites::new(sites))
 }
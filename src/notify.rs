@@ -0,0 +1,206 @@
+use rocket::serde::Serialize;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::NotifyConfig;
+use crate::signing;
+
+/// how long one attempt at delivering may take
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// wait before the first retry, doubled for each one after it
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// the JSON body POSTed to each url after a deploy
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DeployEvent {
+    pub site: String,
+    pub deploy_id: u64,
+    pub trigger: &'static str,
+    /// `succeeded` or `failed`
+    pub status: &'static str,
+    /// the commit that went live, unset when the deploy failed
+    pub commit: Option<String>,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+/// delivers `event` to every target in turn, each with its own retries.
+/// blocks until all of them have taken it or been given up on
+pub fn send(targets: &[NotifyConfig], event: &DeployEvent) {
+    let body = match serde_json::to_vec(event) {
+        Ok(x) => x,
+        Err(e) => return error!("failed to encode deploy event: {}", e),
+    };
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    for target in targets {
+        if let Err(e) = deliver(&agent, target, &body) {
+            warn!(
+                "failed to notify {} of deploy {}: {}",
+                target.url, event.deploy_id, e
+            );
+        }
+    }
+}
+
+fn deliver(agent: &ureq::Agent, target: &NotifyConfig, body: &[u8]) -> Result<(), String> {
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        let mut request = agent
+            .post(&target.url)
+            .set("Content-Type", "application/json")
+            .set("X-Bloghoster-Event", "deploy");
+        if let Some(secret) = &target.secret {
+            request = request.set("X-Bloghoster-Signature-256", &signature(secret, body));
+        }
+        match request.send_bytes(body) {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt < target.retries && retryable(&e) => {
+                warn!("notifying {} failed, retrying: {}", target.url, e);
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// a client error won't go any better the second time, apart from these
+fn retryable(error: &ureq::Error) -> bool {
+    match error {
+        ureq::Error::Status(code, _) => *code >= 500 || *code == 408 || *code == 429,
+        ureq::Error::Transport(_) => true,
+    }
+}
+
+/// signs `body` the same way GitHub signs its webhooks so receivers can
+/// reuse their verification
+fn signature(secret: &str, body: &[u8]) -> String {
+    format!("sha256={}", signing::sign(secret, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    /// what the stand-in was sent, the headers lowercased
+    struct Received {
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Received {
+        fn header(&self, name: &str) -> Option<&str> {
+            let name = name.to_ascii_lowercase();
+            self.headers
+                .iter()
+                .find(|x| x.0 == name)
+                .map(|x| x.1.as_str())
+        }
+    }
+
+    /// answers one request per status in turn and hands back what it got
+    fn stand_in(statuses: &[u16]) -> (String, JoinHandle<Vec<Received>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let statuses = statuses.to_vec();
+        let handle = thread::spawn(move || {
+            let mut received = Vec::new();
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut headers = Vec::new();
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(':') {
+                        Some((name, value)) => headers
+                            .push((name.trim().to_ascii_lowercase(), value.trim().to_string())),
+                        None => break,
+                    }
+                }
+                let length = headers
+                    .iter()
+                    .find(|x| x.0 == "content-length")
+                    .map_or(0, |x| x.1.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                received.push(Received { headers, body });
+            }
+            received
+        });
+        (url, handle)
+    }
+
+    fn event() -> DeployEvent {
+        DeployEvent {
+            site: "blog".to_string(),
+            deploy_id: 7,
+            trigger: "webhook",
+            status: "succeeded",
+            commit: Some("abc".to_string()),
+            duration_ms: 12,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn signs_and_retries_after_a_server_error() {
+        let (url, stand_in) = stand_in(&[503, 200]);
+        let target = NotifyConfig {
+            url,
+            secret: Some("secret".to_string()),
+            retries: 2,
+        };
+        send(&[target], &event());
+
+        let received = stand_in.join().unwrap();
+        assert_eq!(received.len(), 2);
+        for request in &received {
+            let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+            mac.update(&request.body);
+            let expected = format!("sha256={}", signing::hex(&mac.finalize().into_bytes()));
+            assert_eq!(
+                request.header("X-Bloghoster-Signature-256"),
+                Some(expected.as_str())
+            );
+            assert_eq!(request.header("X-Bloghoster-Event"), Some("deploy"));
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            assert_eq!(body["deploy_id"], 7);
+            assert_eq!(body["status"], "succeeded");
+        }
+    }
+
+    #[test]
+    fn gives_up_on_a_client_error() {
+        let (url, stand_in) = stand_in(&[404]);
+        let target = NotifyConfig {
+            url,
+            secret: None,
+            retries: 2,
+        };
+        let started = std::time::Instant::now();
+        send(&[target], &event());
+        assert!(started.elapsed() < RETRY_DELAY);
+
+        let received = stand_in.join().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].header("X-Bloghoster-Signature-256"), None);
+    }
+}
@@ -0,0 +1,121 @@
+use git2::{Oid, Repository};
+use rand::Rng;
+use rocket::tokio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::auth;
+use crate::config::SiteConfig;
+use crate::deploy::{Deployer, Trigger};
+use crate::pull;
+use crate::release;
+use crate::tags;
+
+/// the longest we'll wait between polls while the remote keeps failing
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// fetches the branch and works out whether the live release is behind
+/// it, and if so the commit that would be deployed. that's the zero id
+/// when nothing has been cloned yet
+fn upstream_changed(site: &SiteConfig) -> Result<Option<Oid>, git2::Error> {
+    let repo = match Repository::open(&site.checkout_dir) {
+        Ok(repo) => repo,
+        // nothing has been cloned yet so a deploy is definitely needed
+        Err(_) => return Ok(Some(Oid::zero())),
+    };
+    let mut remote = repo.find_remote("origin")?;
+    let callbacks = auth::callbacks(&site.credentials);
+    let upstream = pull::do_fetch(
+        &repo,
+        &[site.branch.as_str()],
+        &mut remote,
+        callbacks,
+        site.depth,
+    )?
+    .id();
+
+    let live = match release::current_commit(&site.live_dir).map(|x| Oid::from_str(&x)) {
+        Some(Ok(x)) => x,
+        _ => return Ok(Some(upstream)),
+    };
+    if let Some(pattern) = &site.tags {
+        let newest = tags::newest(&repo, pattern)?;
+        return Ok(newest.map(|(_, commit)| commit.id()).filter(|x| *x != live));
+    }
+    // with the merge strategy the live commit is a merge on top of upstream
+    let up_to_date = live == upstream || repo.graph_descendant_of(live, upstream)?;
+    Ok((!up_to_date).then_some(upstream))
+}
+
+/// how long to wait after `failures` polls in a row have failed, doubling
+/// each time with some jitter so a flaky host isn't hit in lockstep
+fn backoff(interval: Duration, failures: u32) -> Duration {
+    let delay = interval
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(MAX_BACKOFF)
+        .max(interval);
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+/// polls the remote every `interval` and queues a deploy when the branch
+/// has moved on from what's live, for hosts that can't send webhooks. a
+/// commit that can't be deployed, like one with conflicts or a failing
+/// build, waits for upstream to move on or for an explicit deploy rather
+/// than failing every poll. other failures are retried with backoff
+pub async fn run(deployer: Arc<Deployer>, interval: Duration) {
+    let mut failures = 0;
+    let mut queued = None;
+    loop {
+        let delay = match failures {
+            0 => interval,
+            n => backoff(interval, n),
+        };
+        tokio::time::sleep(delay).await;
+
+        // previews carry on while a rollback pins the site, this is also
+        // what notices branches deleted without a webhook
+        if deployer.site().preview.is_some() {
+            deployer.request_previews();
+        }
+
+        if deployer.is_pinned().await {
+            continue;
+        }
+
+        let checked = {
+            let _guard = deployer.lock_repo().await;
+            let site = deployer.site().clone();
+            tokio::task::spawn_blocking(move || upstream_changed(&site)).await
+        };
+
+        match checked {
+            Ok(Ok(changed)) => {
+                let retry = changed.is_some_and(|x| deployer.last_attempted() != Some(x));
+                // trying again after the deploy we queued failed, or a
+                // preview did, backs off the same as a failing poll
+                let failed = queued.take().is_some_and(|id| {
+                    deployer
+                        .last_outcome()
+                        .is_some_and(|x| x.deploy_id == id && x.error.is_some())
+                });
+                failures = match (retry && failed) || deployer.previews_failed() {
+                    true => failures + 1,
+                    false => 0,
+                };
+                if retry {
+                    let ticket = deployer.request(Trigger::Poll);
+                    info!("upstream changed, queued deploy {}", ticket.deploy_id);
+                    queued = Some(ticket.deploy_id);
+                }
+            }
+            Ok(Err(e)) => {
+                failures += 1;
+                warn!("polling the remote failed {} time(s): {}", failures, e);
+            }
+            Err(e) => {
+                failures += 1;
+                error!("poll task panicked: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use git2::{BranchType, Oid, Repository};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::auth;
+use crate::config::{PreviewConfig, SiteConfig};
+use crate::deploy::{prepare_release, DeployError};
+use crate::glob;
+use crate::pull;
+use crate::release;
+use crate::verify;
+
+/// the subdomain a branch is previewed on, anything that isn't a letter or
+/// digit becomes a `-` so `drafts/New_Post` ends up on `drafts-new-post`
+pub fn label(branch: &str) -> String {
+    let label: String = branch
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_lowercase(),
+            _ => '-',
+        })
+        .take(63)
+        .collect();
+    label.trim_matches('-').to_string()
+}
+
+/// whether a push to `branch` should update the previews
+pub fn wants(site: &SiteConfig, branch: &str) -> bool {
+    match &site.preview {
+        Some(preview) => branch != site.branch && glob::matches(&preview.branches, branch),
+        None => false,
+    }
+}
+
+/// the preview label out of a `<label>.preview.<domain>` host
+pub fn host_label(preview: &PreviewConfig, host: &str) -> Option<String> {
+    let suffix = format!(".preview.{}", preview.domain.as_deref()?);
+    let split = host.len().checked_sub(suffix.len())?;
+    let (label, rest) = (host.get(..split)?, host.get(split..)?);
+    if label.is_empty() || label.contains('.') || !rest.eq_ignore_ascii_case(&suffix) {
+        return None;
+    }
+    Some(label.to_ascii_lowercase())
+}
+
+/// symlinks to each preview's release, named by label
+fn links_dir(preview: &PreviewConfig) -> PathBuf {
+    preview.dir.join("branches")
+}
+
+/// the directory a preview is served from
+pub fn served_path(site: &SiteConfig, label: &str) -> Option<PathBuf> {
+    let preview = site.preview.as_ref()?;
+    Some(links_dir(preview).join(label).join(site.served_dir()))
+}
+
+/// fetches every branch and brings the previews in line with them. branches
+/// that moved are exported and built again, previews of branches that have
+/// been deleted upstream are removed along with their releases. `attempted`
+/// holds the commit last tried for each label, those aren't tried again.
+/// a preview that failed in a way that may pass is retried on the next
+/// sync and the last such error returned once every branch has had its turn
+pub fn sync(site: &SiteConfig, attempted: &mut HashMap<String, Oid>) -> Result<(), DeployError> {
+    let preview = match &site.preview {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let repo = match Repository::open(&site.checkout_dir) {
+        Ok(repo) => repo,
+        // the site's first deploy clones it, previews follow after that
+        Err(_) => return Ok(()),
+    };
+
+    let mut remote = repo.find_remote("origin").map_err(DeployError::Fetch)?;
+    let callbacks = auth::callbacks(&site.credentials);
+    pull::fetch_pruned(
+        &["+refs/heads/*:refs/remotes/origin/*"],
+        &mut remote,
+        callbacks,
+        site.depth,
+    )
+    .map_err(DeployError::Fetch)?;
+
+    let mut wanted = BTreeMap::new();
+    for branch in repo
+        .branches(Some(BranchType::Remote))
+        .map_err(DeployError::Fetch)?
+    {
+        let (branch, _) = branch.map_err(DeployError::Fetch)?;
+        let name = match branch.name() {
+            Ok(Some(name)) => name.strip_prefix("origin/").unwrap_or(name),
+            _ => continue,
+        };
+        let label = label(name);
+        if name == "HEAD" || label.is_empty() || !wants(site, name) {
+            continue;
+        }
+        let commit = branch
+            .get()
+            .peel_to_commit()
+            .map_err(DeployError::Checkout)?;
+        if let Some((other, _)) = wanted.insert(label.clone(), (name.to_string(), commit)) {
+            warn!(
+                "branches {} and {} are both previewed on {}",
+                other, name, label
+            );
+        }
+    }
+
+    let links = links_dir(preview);
+    let releases = preview.dir.join("releases");
+    fs::create_dir_all(&links).map_err(DeployError::Release)?;
+
+    let mut retry = None;
+    for (label, (branch, commit)) in &wanted {
+        let link = links.join(label);
+        if release::current_commit(&link) == Some(commit.id().to_string())
+            || attempted.get(label) == Some(&commit.id())
+        {
+            continue;
+        }
+        info!("deploying preview of {} at {}", branch, commit.id());
+        let deployed = verify::check(site, &repo, commit.id()).and_then(|_| {
+            release::export(&repo, commit, &releases, &site.sparse_paths, |dir| {
+                prepare_release(site, &repo, commit, dir)?;
+                Ok(())
+            })
+            .and_then(|dir| release::activate(&dir, &link).map_err(DeployError::Release))
+        });
+        // one broken branch shouldn't hold up the others
+        match deployed {
+            Err(e) if !e.is_deterministic() => {
+                error!("preview of {} failed, will retry: {}", branch, e);
+                retry = Some(e);
+                continue;
+            }
+            Err(e) => error!("preview of {} failed: {}", branch, e),
+            Ok(()) => {}
+        }
+        attempted.insert(label.clone(), commit.id());
+    }
+
+    attempted.retain(|label, _| wanted.contains_key(label));
+    remove_stale(&links, &releases, |label| wanted.contains_key(label))
+        .map_err(DeployError::Release)?;
+    retry.map_or(Ok(()), Err)
+}
+
+/// removes previews `keep` says no to and then any release no preview
+/// points at anymore
+fn remove_stale(links: &Path, releases: &Path, keep: impl Fn(&str) -> bool) -> io::Result<()> {
+    let mut used = HashSet::new();
+    for entry in fs::read_dir(links)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        if keep(&name) {
+            used.extend(release::current(&entry.path()));
+        } else {
+            info!("removing the preview on {}, its branch is gone", name);
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    if !releases.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(releases)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = fs::canonicalize(entry.path())?;
+        if !used.contains(&path) {
+            fs::remove_dir_all(path)?;
+        }
+    }
+    Ok(())
+}
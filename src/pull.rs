@@ -15,15 +15,91 @@
 
 use git2::Repository;
 use std::io::{self, Write};
+use std::path::Path;
 use std::str;
 
+/// clones `branch` of `url` into `path`, only the last `depth` commits of
+/// it when that's set and only `paths` of the working tree when given
+pub fn clone(
+    url: &str,
+    branch: &str,
+    path: &Path,
+    cb: git2::RemoteCallbacks,
+    depth: Option<u32>,
+    paths: &[String],
+) -> Result<Repository, git2::Error> {
+    let mut fo = git2::FetchOptions::new();
+    fo.remote_callbacks(cb);
+    if let Some(depth) = depth {
+        fo.depth(i32::try_from(depth).unwrap_or(i32::MAX));
+    }
+    git2::build::RepoBuilder::new()
+        .fetch_options(fo)
+        .with_checkout(checkout(paths))
+        .branch(branch)
+        .clone(url, path)
+}
+
+/// a checkout of just `paths`, or of everything when there are none
+pub fn checkout<'cb>(paths: &[String]) -> git2::build::CheckoutBuilder<'cb> {
+    let mut builder = git2::build::CheckoutBuilder::new();
+    for path in paths {
+        builder.path(path.trim_end_matches('/'));
+    }
+    builder
+}
+
+/// whether a file or directory at `path` is part of a checkout of `paths`
+pub fn checks_out(paths: &[String], path: &Path) -> bool {
+    // a directory holding one of the paths has to be there too
+    paths.is_empty()
+        || paths
+            .iter()
+            .any(|x| path.starts_with(x) || Path::new(x).starts_with(path))
+}
+
 pub fn do_fetch<'a>(
     repo: &'a git2::Repository,
     refs: &[&str],
-    remote: &'a mut git2::Remote,
+    remote: &mut git2::Remote,
+    cb: git2::RemoteCallbacks,
+    depth: Option<u32>,
 ) -> Result<git2::AnnotatedCommit<'a>, git2::Error> {
-    let mut cb = git2::RemoteCallbacks::new();
+    fetch(refs, remote, cb, git2::FetchPrune::Unspecified, depth)?;
+
+    // FETCH_HEAD also lists every tag that came along, the first entry is
+    // often one of those rather than the branch that was asked for
+    let mut fetched = None;
+    repo.fetchhead_foreach(|_, _, oid, is_merge| {
+        if is_merge && fetched.is_none() {
+            fetched = Some(*oid);
+        }
+        true
+    })?;
+    match fetched {
+        Some(oid) => repo.find_annotated_commit(oid),
+        None => Err(git2::Error::from_str("the fetch didn't return the branch")),
+    }
+}
 
+/// fetches `refs` and deletes remote-tracking branches that are gone from
+/// the remote
+pub fn fetch_pruned(
+    refs: &[&str],
+    remote: &mut git2::Remote,
+    cb: git2::RemoteCallbacks,
+    depth: Option<u32>,
+) -> Result<(), git2::Error> {
+    fetch(refs, remote, cb, git2::FetchPrune::On, depth)
+}
+
+fn fetch(
+    refs: &[&str],
+    remote: &mut git2::Remote,
+    mut cb: git2::RemoteCallbacks,
+    prune: git2::FetchPrune,
+    depth: Option<u32>,
+) -> Result<(), git2::Error> {
     // Print out our transfer progress.
     cb.transfer_progress(|stats| {
         if stats.received_objects() == stats.total_objects() {
@@ -41,16 +117,22 @@ pub fn do_fetch<'a>(
                 stats.received_bytes()
             );
         }
-        io::stdout().flush().unwrap();
+        let _ = io::stdout().flush();
         true
     });
 
     let mut fo = git2::FetchOptions::new();
     fo.remote_callbacks(cb);
+    fo.prune(prune);
+    // a shallow repository stays shallow, each fetch only adds the history
+    // it has to
+    if let Some(depth) = depth {
+        fo.depth(i32::try_from(depth).unwrap_or(i32::MAX));
+    }
     // Always fetch all tags.
     // Perform a download and also update tips
     fo.download_tags(git2::AutotagOption::All);
-    println!("Fetching {} for repo", remote.name().unwrap());
+    println!("Fetching {} for repo", remote.name().unwrap_or("remote"));
     remote.fetch(refs, Some(&mut fo), None)?;
 
     // If there are local objects (we got a thin pack), then tell the user
@@ -73,15 +155,36 @@ pub fn do_fetch<'a>(
             stats.received_bytes()
         );
     }
+    Ok(())
+}
 
-    let fetch_head = repo.find_reference("FETCH_HEAD")?;
-    Ok(repo.reference_to_annotated_commit(&fetch_head)?)
+/// points the local branch at the fetched commit and forces the working tree
+/// to match it, local commits, changes and untracked files are all discarded
+pub fn hard_reset(
+    repo: &Repository,
+    remote_branch: &str,
+    fetch_commit: &git2::AnnotatedCommit,
+    paths: &[String],
+) -> Result<(), git2::Error> {
+    let refname = format!("refs/heads/{}", remote_branch);
+    let commit = repo.find_commit(fetch_commit.id())?;
+    let msg = format!("Reset: Setting {} to id: {}", refname, commit.id());
+    println!("{}", msg);
+    repo.reference(&refname, commit.id(), true, &msg)?;
+    repo.set_head(&refname)?;
+    repo.reset(
+        commit.as_object(),
+        git2::ResetType::Hard,
+        Some(checkout(paths).force().remove_untracked(true)),
+    )?;
+    Ok(())
 }
 
 pub fn fast_forward(
     repo: &Repository,
     lb: &mut git2::Reference,
     rc: &git2::AnnotatedCommit,
+    paths: &[String],
 ) -> Result<(), git2::Error> {
     let name = match lb.name() {
         Some(s) => s.to_string(),
@@ -92,7 +195,7 @@ pub fn fast_forward(
     lb.set_target(rc.id(), &msg)?;
     repo.set_head(&name)?;
     repo.checkout_head(Some(
-        git2::build::CheckoutBuilder::default()
+        checkout(paths)
             // For some reason the force is required to make the working directory actually get updated
             // I suspect we should be adding some logic to handle dirty working directory states
             // but this is just an example so maybe not.
@@ -105,6 +208,7 @@ pub fn normal_merge(
     repo: &Repository,
     local: &git2::AnnotatedCommit,
     remote: &git2::AnnotatedCommit,
+    paths: &[String],
 ) -> Result<(), git2::Error> {
     let local_tree = repo.find_commit(local.id())?.tree()?;
     let remote_tree = repo.find_commit(remote.id())?.tree()?;
@@ -114,9 +218,12 @@ pub fn normal_merge(
     let mut idx = repo.merge_trees(&ancestor, &local_tree, &remote_tree, None)?;
 
     if idx.has_conflicts() {
-        println!("Merge conflicts detected...");
-        repo.checkout_index(Some(&mut idx), None)?;
-        return Ok(());
+        // leave the working tree alone rather than serving conflict markers
+        return Err(git2::Error::new(
+            git2::ErrorCode::MergeConflict,
+            git2::ErrorClass::Merge,
+            "merge conflicts detected",
+        ));
     }
     let result_tree = repo.find_tree(idx.write_tree_to(repo)?)?;
     // now create the merge commit
@@ -134,7 +241,7 @@ pub fn normal_merge(
         &[&local_commit, &remote_commit],
     )?;
     // Set working tree to match head.
-    repo.checkout_head(None)?;
+    repo.checkout_head(Some(&mut checkout(paths)))?;
     Ok(())
 }
 
@@ -142,6 +249,7 @@ pub fn do_merge<'a>(
     repo: &'a Repository,
     remote_branch: &str,
     fetch_commit: git2::AnnotatedCommit<'a>,
+    paths: &[String],
 ) -> Result<(), git2::Error> {
     // 1. do a merge analysis
     let analysis = repo.merge_analysis(&[&fetch_commit])?;
@@ -153,7 +261,7 @@ pub fn do_merge<'a>(
         let refname = format!("refs/heads/{}", remote_branch);
         match repo.find_reference(&refname) {
             Ok(mut r) => {
-                fast_forward(repo, &mut r, &fetch_commit)?;
+                fast_forward(repo, &mut r, &fetch_commit, paths)?;
             }
             Err(_) => {
                 // The branch doesn't exist so just set the reference to the
@@ -167,7 +275,7 @@ pub fn do_merge<'a>(
                 )?;
                 repo.set_head(&refname)?;
                 repo.checkout_head(Some(
-                    git2::build::CheckoutBuilder::default()
+                    checkout(paths)
                         .allow_conflicts(true)
                         .conflict_style_merge(true)
                         .force(),
@@ -177,7 +285,7 @@ pub fn do_merge<'a>(
     } else if analysis.0.is_normal() {
         // do a normal merge
         let head_commit = repo.reference_to_annotated_commit(&repo.head()?)?;
-        normal_merge(&repo, &head_commit, &fetch_commit)?;
+        normal_merge(&repo, &head_commit, &fetch_commit, paths)?;
     } else {
         println!("Nothing to do...");
     }
@@ -0,0 +1,268 @@
+use chrono::{DateTime, Utc};
+use rocket::http::ContentType;
+use rocket::tokio::fs::File;
+use rocket::tokio::io::{self, AsyncRead, AsyncReadExt, AsyncSeekExt};
+use std::io::{Cursor, SeekFrom};
+use std::path::Path;
+
+/// a request asking for more ranges than this gets the whole file, a
+/// client seeking through media never needs many
+const MAX_RANGES: usize = 16;
+
+pub type Reader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// a span of bytes, both ends included
+#[derive(Debug, Clone, Copy)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// what to send back for a Range request that's being honoured
+pub enum Partial {
+    /// 206 with the one range as the body
+    Single {
+        range: ByteRange,
+        total: u64,
+        body: Reader,
+    },
+    /// 206 with a multipart/byteranges body holding each range
+    Multiple {
+        boundary: String,
+        length: u64,
+        body: Reader,
+    },
+    /// 416, none of the ranges are inside the file
+    Unsatisfiable { total: u64 },
+}
+
+/// the ranges a Range header asks for out of `total` bytes, ones that
+/// start past the end are left out. `None` when the header should be
+/// ignored and the whole file sent, for units other than bytes, bad syntax
+/// or too many ranges
+pub fn parse(header: &str, total: u64) -> Option<Vec<ByteRange>> {
+    let specs = header.trim().strip_prefix("bytes=")?;
+    let mut ranges = Vec::new();
+    for (i, spec) in specs.split(',').map(str::trim).enumerate() {
+        if i >= MAX_RANGES {
+            return None;
+        }
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        if start.is_empty() {
+            // a suffix, the last so many bytes
+            let suffix: u64 = end.parse().ok()?;
+            if suffix > 0 && total > 0 {
+                ranges.push(ByteRange {
+                    start: total.saturating_sub(suffix),
+                    end: total - 1,
+                });
+            }
+            continue;
+        }
+        let start: u64 = start.parse().ok()?;
+        let end = match end {
+            "" => u64::MAX,
+            x => x.parse().ok()?,
+        };
+        if end < start {
+            return None;
+        }
+        if start < total {
+            ranges.push(ByteRange {
+                start,
+                end: end.min(total - 1),
+            });
+        }
+    }
+    Some(ranges)
+}
+
+/// whether an If-Range validator still matches, so the range can be sent
+/// rather than the whole file. only strong validators count
+pub fn if_range(value: &str, etag: Option<&str>, modified: Option<DateTime<Utc>>) -> bool {
+    let value = value.trim();
+    if value.starts_with('"') {
+        return etag == Some(value);
+    }
+    if value.starts_with("W/") {
+        return false;
+    }
+    match (DateTime::parse_from_rfc2822(value), modified) {
+        (Ok(date), Some(modified)) => date.timestamp() == modified.timestamp(),
+        _ => false,
+    }
+}
+
+/// reads the ranges of the file at `path`, which is `total` bytes long
+pub async fn read(
+    path: &Path,
+    total: u64,
+    ranges: Vec<ByteRange>,
+    content_type: Option<&ContentType>,
+) -> io::Result<Partial> {
+    match ranges[..] {
+        [] => Ok(Partial::Unsatisfiable { total }),
+        [range] => Ok(Partial::Single {
+            range,
+            total,
+            body: slice(path, range).await?,
+        }),
+        _ => {
+            let boundary = format!("{:016x}", rand::random::<u64>());
+            let content_type = content_type.cloned().unwrap_or(ContentType::Binary);
+            let mut length = 0;
+            let mut body: Reader = Box::new(io::empty());
+            for range in ranges {
+                let head = format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary, content_type, range.start, range.end, total
+                );
+                length += head.len() as u64 + range.len();
+                body = Box::new(
+                    body.chain(Cursor::new(head))
+                        .chain(slice(path, range).await?),
+                );
+            }
+            let tail = format!("\r\n--{}--\r\n", boundary);
+            length += tail.len() as u64;
+            Ok(Partial::Multiple {
+                boundary,
+                length,
+                body: Box::new(body.chain(Cursor::new(tail))),
+            })
+        }
+    }
+}
+
+async fn slice(path: &Path, range: ByteRange) -> io::Result<Reader> {
+    let mut file = File::open(path).await?;
+    file.seek(SeekFrom::Start(range.start)).await?;
+    Ok(Box::new(file.take(range.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(header: &str, total: u64) -> Option<Vec<(u64, u64)>> {
+        parse(header, total).map(|x| x.iter().map(|x| (x.start, x.end)).collect())
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(spans("bytes=0-99", 1000), Some(vec![(0, 99)]));
+        assert_eq!(spans("bytes=0-0, 5-9", 1000), Some(vec![(0, 0), (5, 9)]));
+        // the end is clamped to the file
+        assert_eq!(spans("bytes=900-2000", 1000), Some(vec![(900, 999)]));
+    }
+
+    #[test]
+    fn parses_open_ended_and_suffix_ranges() {
+        assert_eq!(spans("bytes=990-", 1000), Some(vec![(990, 999)]));
+        assert_eq!(spans("bytes=-10", 1000), Some(vec![(990, 999)]));
+        assert_eq!(spans("bytes=-5000", 1000), Some(vec![(0, 999)]));
+        assert_eq!(spans("bytes=-0", 1000), Some(vec![]));
+        assert_eq!(spans("bytes=-10", 0), Some(vec![]));
+    }
+
+    #[test]
+    fn leaves_out_ranges_past_the_end() {
+        assert_eq!(spans("bytes=1000-", 1000), Some(vec![]));
+        assert_eq!(spans("bytes=0-9, 2000-3000", 1000), Some(vec![(0, 9)]));
+    }
+
+    #[test]
+    fn ignores_headers_it_cant_use() {
+        assert_eq!(spans("items=0-9", 1000), None);
+        assert_eq!(spans("bytes=9-0", 1000), None);
+        assert_eq!(spans("bytes=a-b", 1000), None);
+        assert_eq!(spans("bytes=0-9,", 1000), None);
+        let many = (0..=MAX_RANGES)
+            .map(|x| format!("{}-{}", x, x))
+            .collect::<Vec<_>>();
+        assert_eq!(spans(&format!("bytes={}", many.join(",")), 1000), None);
+        let enough = &many[..MAX_RANGES];
+        assert!(spans(&format!("bytes={}", enough.join(",")), 1000).is_some());
+    }
+
+    #[test]
+    fn if_range_only_takes_strong_validators() {
+        let modified = DateTime::parse_from_rfc2822("Tue, 01 Oct 2024 10:00:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert!(if_range("\"abc\"", Some("\"abc\""), None));
+        assert!(!if_range("\"abd\"", Some("\"abc\""), None));
+        assert!(!if_range("W/\"abc\"", Some("\"abc\""), None));
+        assert!(if_range(
+            "Tue, 01 Oct 2024 10:00:00 GMT",
+            None,
+            Some(modified)
+        ));
+        assert!(!if_range(
+            "Tue, 01 Oct 2024 09:00:00 GMT",
+            None,
+            Some(modified)
+        ));
+    }
+
+    async fn body(mut reader: Reader) -> String {
+        let mut text = String::new();
+        reader.read_to_string(&mut text).await.unwrap();
+        text
+    }
+
+    #[rocket::async_test]
+    async fn reads_ranges() {
+        let path = std::env::temp_dir().join(format!("bloghoster-ranges-{}", std::process::id()));
+        std::fs::write(&path, "0123456789").unwrap();
+
+        match read(&path, 10, vec![], None).await.unwrap() {
+            Partial::Unsatisfiable { total } => assert_eq!(total, 10),
+            _ => panic!("expected a 416"),
+        }
+
+        let range = ByteRange { start: 2, end: 4 };
+        match read(&path, 10, vec![range], None).await.unwrap() {
+            Partial::Single {
+                range,
+                total,
+                body: reader,
+            } => {
+                assert_eq!((range.start, range.end, total), (2, 4, 10));
+                assert_eq!(body(reader).await, "234");
+            }
+            _ => panic!("expected a single range"),
+        }
+
+        let ranges = vec![
+            ByteRange { start: 0, end: 1 },
+            ByteRange { start: 8, end: 9 },
+        ];
+        match read(&path, 10, ranges, Some(&ContentType::Plain))
+            .await
+            .unwrap()
+        {
+            Partial::Multiple {
+                boundary,
+                length,
+                body: reader,
+            } => {
+                let text = body(reader).await;
+                assert_eq!(text.len() as u64, length);
+                assert!(text.contains("Content-Range: bytes 0-1/10\r\n\r\n01\r\n"));
+                assert!(text.contains("Content-Range: bytes 8-9/10\r\n\r\n89\r\n"));
+                assert!(text.contains("Content-Type: text/plain"));
+                assert!(text.ends_with(&format!("\r\n--{}--\r\n", boundary)));
+            }
+            _ => panic!("expected multiple ranges"),
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+}
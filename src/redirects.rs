@@ -0,0 +1,294 @@
+use rocket::response::Redirect;
+use std::path::Path;
+
+use crate::files;
+
+/// the file in the served directory the rules are read from
+pub const FILE: &str = "_redirects";
+
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    /// `:name`, matches any one segment
+    Placeholder(String),
+}
+
+/// one line of a `_redirects` file
+#[derive(Debug)]
+pub struct Rule {
+    from: Vec<Segment>,
+    /// the pattern ends in `*`, which matches the rest of the path
+    splat: bool,
+    to: String,
+    pub status: u16,
+    /// applies even when there's a file at the path, written as `301!`
+    pub force: bool,
+}
+
+/// a rule that matched a request
+pub struct Matched<'a> {
+    pub rule: &'a Rule,
+    /// where to send the request, with the placeholders filled in
+    pub to: String,
+}
+
+/// parses a `_redirects` file, each line being `from to [status]`. lines
+/// that can't be used are returned as problems rather than failing the
+/// whole file, the same as netlify
+fn parse(text: &str) -> (Vec<Rule>, Vec<String>) {
+    let mut rules = Vec::new();
+    let mut problems = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_rule(line) {
+            Ok(rule) => rules.push(rule),
+            Err(e) => problems.push(format!("line {}: {}", number + 1, e)),
+        }
+    }
+    (rules, problems)
+}
+
+fn parse_rule(line: &str) -> Result<Rule, String> {
+    let fields: Vec<_> = line.split_whitespace().collect();
+    let (from, to, status) = match fields[..] {
+        [from, to] => (from, to, "301"),
+        [from, to, status] => (from, to, status),
+        _ if fields.iter().any(|x| x.contains('=')) => {
+            return Err("query and condition matching isn't supported".to_string())
+        }
+        _ => return Err("expected a source, a destination and maybe a status".to_string()),
+    };
+    if !from.starts_with('/') {
+        return Err("only paths can be redirected, not domains".to_string());
+    }
+
+    let (status, force) = match status.strip_suffix('!') {
+        Some(x) => (x, true),
+        None => (status, false),
+    };
+    let status: u16 = status
+        .parse()
+        .map_err(|_| format!("{} isn't a status", status))?;
+    match status {
+        200 if !to.starts_with('/') => {
+            return Err("a rewrite has to stay on the site, proxying isn't supported".to_string())
+        }
+        200 | 301 | 302 | 303 | 307 | 308 => {}
+        _ => return Err(format!("status {} isn't supported", status)),
+    }
+
+    let mut segments: Vec<_> = from.split('/').filter(|x| !x.is_empty()).collect();
+    let splat = segments.last() == Some(&"*");
+    if splat {
+        segments.pop();
+    }
+    if segments.iter().any(|x| x.contains('*')) {
+        return Err("`*` only works at the end of the source".to_string());
+    }
+    let from = segments
+        .into_iter()
+        .map(|x| match x.strip_prefix(':') {
+            Some(name) => Segment::Placeholder(name.to_string()),
+            None => Segment::Literal(x.to_string()),
+        })
+        .collect();
+    Ok(Rule {
+        from,
+        splat,
+        to: to.to_string(),
+        status,
+        force,
+    })
+}
+
+impl Rule {
+    /// the placeholders in the rule and what they matched in `path`, if
+    /// it matches at all. a trailing slash makes no difference
+    fn capture<'p>(&self, path: &'p str) -> Option<Vec<(&str, &'p str)>> {
+        let mut captured = Vec::new();
+        let mut rest = path.trim_start_matches('/');
+        for segment in &self.from {
+            if rest.is_empty() {
+                return None;
+            }
+            let (part, after) = rest.split_once('/').unwrap_or((rest, ""));
+            match segment {
+                Segment::Literal(x) if x == part => {}
+                Segment::Literal(_) => return None,
+                Segment::Placeholder(name) => captured.push((name.as_str(), part)),
+            }
+            rest = after;
+        }
+        if self.splat {
+            captured.push(("splat", rest));
+        } else if !rest.trim_end_matches('/').is_empty() {
+            return None;
+        }
+        Some(captured)
+    }
+}
+
+impl Matched<'_> {
+    /// the redirect to answer with, the request's query string is passed
+    /// along unless the destination has one of its own
+    pub fn redirect(&self, query: Option<&str>) -> Redirect {
+        let to = match query {
+            Some(query) if !self.to.contains('?') => format!("{}?{}", self.to, query),
+            _ => self.to.clone(),
+        };
+        match self.rule.status {
+            302 => Redirect::found(to),
+            303 => Redirect::to(to),
+            307 => Redirect::temporary(to),
+            308 => Redirect::permanent(to),
+            _ => Redirect::moved(to),
+        }
+    }
+}
+
+/// the first rule matching `path`, with its destination filled in
+pub fn find<'a>(rules: &'a [Rule], path: &str) -> Option<Matched<'a>> {
+    rules.iter().find_map(|rule| {
+        let captured = rule.capture(path)?;
+        Some(Matched {
+            rule,
+            to: fill(&rule.to, &captured),
+        })
+    })
+}
+
+/// swaps each `:name` in `to` for what it matched, anything that isn't a
+/// known placeholder (like the port in a url) is left alone
+fn fill(to: &str, captured: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(to.len());
+    let mut rest = to;
+    while let Some(i) = rest.find(':') {
+        filled.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let end = after
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(after.len());
+        match captured.iter().find(|x| x.0 == &after[..end]) {
+            Some((_, value)) if end > 0 => filled.push_str(value),
+            _ => {
+                filled.push(':');
+                filled.push_str(&after[..end]);
+            }
+        }
+        rest = &after[end..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// the rules of a release, for `PerRelease`
+pub fn load(dir: &Path) -> Vec<Rule> {
+    files::read_site_file(dir, FILE, parse)
+}
+
+pub fn check(dir: &Path) {
+    files::check_site_file(dir, FILE, parse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(text: &str) -> Vec<Rule> {
+        let (rules, problems) = parse(text);
+        assert!(problems.is_empty(), "{:?}", problems);
+        rules
+    }
+
+    fn destination(rules: &[Rule], path: &str) -> Option<String> {
+        find(rules, path).map(|x| x.to)
+    }
+
+    #[test]
+    fn parses_statuses() {
+        let rules = rules(
+            "# comment\n\n/a /b\n/c /d 302\n/e /f 200\n/g /h 308!\n/i https://example.com/ 307\n",
+        );
+        let statuses: Vec<_> = rules.iter().map(|x| (x.status, x.force)).collect();
+        assert_eq!(
+            statuses,
+            [
+                (301, false),
+                (302, false),
+                (200, false),
+                (308, true),
+                (307, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_lines_it_cant_use() {
+        let (rules, problems) = parse(
+            "/ok /fine\n/only\n/q id=:id /x 301\nexample.com /x\n/a /b 418\n/a /b abc\n/a https://x 200\n/a*b /c\n",
+        );
+        assert_eq!(rules.len(), 1);
+        let lines: Vec<_> = problems
+            .iter()
+            .map(|x| x.split(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            ["line 2", "line 3", "line 4", "line 5", "line 6", "line 7", "line 8"]
+        );
+    }
+
+    #[test]
+    fn matches_literal_paths() {
+        let rules = rules("/old /new\n");
+        assert_eq!(destination(&rules, "/old").as_deref(), Some("/new"));
+        assert_eq!(destination(&rules, "/old/").as_deref(), Some("/new"));
+        assert_eq!(destination(&rules, "/older"), None);
+        assert_eq!(destination(&rules, "/old/more"), None);
+        assert_eq!(destination(&rules, "/"), None);
+    }
+
+    #[test]
+    fn fills_placeholders_and_splats() {
+        let rules = rules(
+            "/news/:year/:slug /posts/:year/:slug.html\n/blog/* /posts/:splat\n/ext https://example.com:8443/:nope\n",
+        );
+        assert_eq!(
+            destination(&rules, "/news/2024/hello").as_deref(),
+            Some("/posts/2024/hello.html")
+        );
+        assert_eq!(destination(&rules, "/news/2024"), None);
+        assert_eq!(
+            destination(&rules, "/blog/2024/05/hi").as_deref(),
+            Some("/posts/2024/05/hi")
+        );
+        assert_eq!(destination(&rules, "/blog").as_deref(), Some("/posts/"));
+        // a port or an unknown name is left as it is
+        assert_eq!(
+            destination(&rules, "/ext").as_deref(),
+            Some("https://example.com:8443/:nope")
+        );
+    }
+
+    #[test]
+    fn the_first_matching_rule_wins() {
+        let rules = rules("/a/special /one\n/a/* /two\n");
+        assert_eq!(destination(&rules, "/a/special").as_deref(), Some("/one"));
+        assert_eq!(destination(&rules, "/a/other").as_deref(), Some("/two"));
+    }
+
+    #[test]
+    fn passes_the_query_along() {
+        let rules = rules("/a /b\n/c /d?x=1\n");
+        let redirect = |path, query| {
+            let matched = find(&rules, path).unwrap();
+            format!("{:?}", matched.redirect(query))
+        };
+        assert!(redirect("/a", Some("q=1")).contains("/b?q=1"));
+        assert!(redirect("/c", Some("q=1")).contains("/d?x=1"));
+        assert!(!redirect("/c", Some("q=1")).contains("q=1"));
+    }
+}
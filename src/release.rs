@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::deploy::DeployError;
+use crate::pull;
+
+/// a cache is emptied once it holds this many entries, ones for releases
+/// that have since been pruned would pile up otherwise
+const MAX_CACHED: usize = 64;
+
+/// something read out of each release, like its `_redirects`, or out of a
+/// file in one, keyed by its real path. a release never changes once it's
+/// in place so it's only read the first time it's asked for after a deploy
+pub struct PerRelease<T, const MAX: usize = MAX_CACHED>(Arc<Mutex<HashMap<PathBuf, Arc<T>>>>);
+
+impl<T, const MAX: usize> Default for PerRelease<T, MAX> {
+    fn default() -> Self {
+        Self(Arc::default())
+    }
+}
+
+impl<T, const MAX: usize> Clone for PerRelease<T, MAX> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: Default, const MAX: usize> PerRelease<T, MAX> {
+    /// what `read` made of the release `dir` is in, which can be reached
+    /// through the live or a preview link
+    pub fn get(&self, dir: &Path, read: impl FnOnce(&Path) -> T) -> Arc<T> {
+        let dir = match fs::canonicalize(dir) {
+            Ok(x) => x,
+            Err(_) => return Arc::default(),
+        };
+        if let Some(x) = self.0.lock().unwrap().get(&dir) {
+            return x.clone();
+        }
+        let value = Arc::new(read(&dir));
+        let mut cached = self.0.lock().unwrap();
+        if cached.len() >= MAX {
+            cached.clear();
+        }
+        cached.insert(dir, value.clone());
+        value
+    }
+}
+
+/// checks `commit` out into its own directory under `releases_dir`, named
+/// by the commit id. the files are written to a scratch directory and
+/// handed to `prepare` before being renamed into place, so a release
+/// directory is always complete. only `paths` are written when there are
+/// any. existing releases are reused as is
+pub fn export(
+    repo: &git2::Repository,
+    commit: &git2::Commit,
+    releases_dir: &Path,
+    paths: &[String],
+    prepare: impl FnOnce(&Path) -> Result<(), DeployError>,
+) -> Result<PathBuf, DeployError> {
+    let id = commit.id().to_string();
+    let release = releases_dir.join(&id);
+    if release.is_dir() {
+        return Ok(release);
+    }
+
+    // libgit2 resolves a relative target dir against the repo, not us
+    fs::create_dir_all(releases_dir).map_err(DeployError::Release)?;
+    let releases_dir = fs::canonicalize(releases_dir).map_err(DeployError::Release)?;
+    let partial = releases_dir.join(format!(".{}.partial", id));
+    if partial.exists() {
+        fs::remove_dir_all(&partial).map_err(DeployError::Release)?;
+    }
+
+    repo.checkout_tree(
+        commit.as_object(),
+        Some(
+            pull::checkout(paths)
+                .target_dir(&partial)
+                .update_index(false)
+                .force(),
+        ),
+    )
+    .map_err(DeployError::Checkout)?;
+    prepare(&partial)?;
+
+    fs::rename(&partial, &release).map_err(DeployError::Release)?;
+    Ok(release)
+}
+
+/// points the `live` symlink at `release`. the new link is made next to
+/// the old one and renamed over it so requests never see a missing or
+/// half updated site
+pub fn activate(release: &Path, live: &Path) -> io::Result<()> {
+    let target = fs::canonicalize(release)?;
+    let name = live
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "live path has no name"))?;
+    let staged = live.with_file_name(format!(".{}.next", name.to_string_lossy()));
+
+    if fs::symlink_metadata(&staged).is_ok() {
+        fs::remove_file(&staged)?;
+    }
+    std::os::unix::fs::symlink(target, &staged)?;
+    fs::rename(&staged, live)
+}
+
+/// the release directory `live` currently points at
+pub fn current(live: &Path) -> Option<PathBuf> {
+    fs::read_link(live).ok()
+}
+
+/// the commit id of the release currently being served
+pub fn current_commit(live: &Path) -> Option<String> {
+    current(live)?
+        .file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+}
+
+/// deletes all but the `keep` most recent releases, the live release is
+/// never removed even when it's an old one
+pub fn prune(releases_dir: &Path, live: &Path, keep: usize) -> io::Result<()> {
+    let current = current(live);
+    let mut releases = Vec::new();
+    for entry in fs::read_dir(releases_dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let modified = entry.metadata()?.modified()?;
+        releases.push((modified, entry.path()));
+    }
+    releases.sort_by_key(|x| std::cmp::Reverse(x.0));
+
+    for (_, path) in releases.into_iter().skip(keep) {
+        let is_live = match (&current, fs::canonicalize(&path)) {
+            (Some(current), Ok(path)) => *current == path,
+            _ => false,
+        };
+        if !is_live {
+            fs::remove_dir_all(path)?;
+        }
+    }
+    Ok(())
+}
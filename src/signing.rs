@@ -0,0 +1,41 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// lowercase hex, the way hashes and signatures are written in headers
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match std::str::from_utf8(pair) {
+            Ok(pair) if pair.len() == 2 => u8::from_str_radix(pair, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+fn mac(secret: &str) -> HmacSha256 {
+    HmacSha256::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any length")
+}
+
+/// the hex encoded hmac-sha256 of `body`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = mac(secret);
+    mac.update(body);
+    hex(&mac.finalize().into_bytes())
+}
+
+/// checks a hex encoded hmac-sha256 of `body`, in constant time
+pub fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = match decode_hex(signature.trim()) {
+        Some(x) => x,
+        None => return false,
+    };
+    let mut mac = mac(secret);
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
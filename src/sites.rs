@@ -0,0 +1,187 @@
+use rocket::http::uri::Host;
+use rocket::http::{Method, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::Redirect;
+use rocket::route::{self, Handler, Route};
+use rocket::{Data, Request};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+use crate::config::SiteConfig;
+use crate::deploy::Deployer;
+use crate::files::{self, BlobIds};
+use crate::gone;
+use crate::preview;
+use crate::redirects::{self, Rule};
+use crate::release::PerRelease;
+
+/// one hosted site with its deploy worker
+pub struct Site {
+    pub config: SiteConfig,
+    pub deployer: Arc<Deployer>,
+}
+
+/// every hosted site, requests are matched to one by their Host header
+pub struct Sites {
+    sites: Vec<Site>,
+}
+
+impl Sites {
+    pub fn new(configs: Vec<SiteConfig>) -> Self {
+        // shared so deploy ids stay unique across every site's history
+        let deploy_ids = Arc::new(AtomicU64::new(1));
+        let sites = configs
+            .into_iter()
+            .map(|config| Site {
+                deployer: Deployer::new(config.clone(), deploy_ids.clone()),
+                config,
+            })
+            .collect();
+        Self { sites }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Site> {
+        self.sites.iter()
+    }
+
+    /// the site serving `host` and the label of the branch preview it's
+    /// for, if it is one. sites without any hostnames catch whatever isn't
+    /// claimed by another site
+    fn resolve(&self, host: Option<&Host<'_>>) -> Option<(&Site, Option<String>)> {
+        if let Some(domain) = host.map(|x| x.domain().as_str()) {
+            for site in &self.sites {
+                let config = &site.config;
+                if config
+                    .hostnames
+                    .iter()
+                    .any(|x| x.eq_ignore_ascii_case(domain))
+                {
+                    return Some((site, None));
+                }
+                let label = config
+                    .preview
+                    .as_ref()
+                    .and_then(|x| preview::host_label(x, domain));
+                if label.is_some() {
+                    return Some((site, label));
+                }
+            }
+        }
+        let fallback = self.sites.iter().find(|x| x.config.hostnames.is_empty());
+        fallback.map(|x| (x, None))
+    }
+
+    pub fn for_host(&self, host: Option<&Host<'_>>) -> Option<&Site> {
+        self.resolve(host).map(|x| x.0)
+    }
+
+    pub fn for_request(&self, req: &Request<'_>) -> Option<&Site> {
+        self.for_host(req.host())
+    }
+
+    /// the directory the request is served from, the live release of its
+    /// site or a branch preview
+    pub fn served_path(&self, req: &Request<'_>) -> Option<PathBuf> {
+        match self.resolve(req.host())? {
+            (site, None) => Some(site.config.public_path()),
+            (site, Some(label)) => preview::served_path(&site.config, &label),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r Site {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let site = req
+            .rocket()
+            .state::<Sites>()
+            .and_then(|x| x.for_request(req));
+        match site {
+            Some(x) => Outcome::Success(x),
+            None => Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+/// the name of the route `SiteFiles` serves files on
+pub const SITE_FILES: &str = "SiteFiles";
+
+/// serves the static files of whichever site or preview the request is for
+#[derive(Clone, Default)]
+pub struct SiteFiles {
+    blob_ids: BlobIds,
+    redirects: PerRelease<Vec<Rule>>,
+    gone: PerRelease<Vec<String>>,
+}
+
+#[rocket::async_trait]
+impl Handler for SiteFiles {
+    async fn handle<'r>(&self, req: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        let served = req
+            .rocket()
+            .state::<Sites>()
+            .and_then(|x| Some((x.for_request(req)?, x.served_path(req)?)));
+        let (site, root) = match served {
+            Some(x) => x,
+            // nothing may be live yet on a first start, the deploy worker
+            // publishes the first release once we're up
+            None => return route::Outcome::forward(data, Status::NotFound),
+        };
+        let clean = site.config.urls.clean;
+        let mut found = files::request_path(req).and_then(|x| files::locate(&root, &x, clean));
+        let url = req.uri().path().as_str();
+        let query = req.uri().query().map(|x| x.as_str());
+
+        let rules = self.redirects.get(&root, redirects::load);
+        let mut rewritten = false;
+        if let Some(matched) = redirects::find(&rules, url) {
+            // like netlify a file at the path wins unless the rule is forced
+            if matched.rule.force || found.is_none() {
+                if matched.rule.status != 200 {
+                    return route::Outcome::from(req, matched.redirect(query));
+                }
+                found = files::locate_url(&root, &matched.to, clean);
+                rewritten = true;
+            }
+        }
+
+        // one url per page, as long as the page really is found there
+        let canonical = match &found {
+            Some(path) if !rewritten => files::canonical(url, path, &site.config.urls)
+                .filter(|x| files::locate_url(&root, x, clean).as_ref() == Some(path)),
+            _ => None,
+        };
+        if let Some(to) = canonical {
+            let to = match query {
+                Some(query) => format!("{}?{}", to, query),
+                None => to,
+            };
+            return route::Outcome::from(req, Redirect::moved(to));
+        }
+
+        let file = match found {
+            Some(path) => files::open(req, &path, &site.config, &self.blob_ids).await,
+            None => None,
+        };
+        match file {
+            Some(file) => route::Outcome::from(req, file),
+            // taken down on purpose, so 410 rather than 404
+            None if gone::is_gone(&self.gone.get(&root, gone::load), url) => {
+                route::Outcome::error(Status::Gone)
+            }
+            None => route::Outcome::forward(data, Status::NotFound),
+        }
+    }
+}
+
+impl From<SiteFiles> for Vec<Route> {
+    fn from(files: SiteFiles) -> Self {
+        // same rank as a plain FileServer so routes still win over files
+        let mut route = Route::ranked(10, Method::Get, "/<path..>", files);
+        route.name = Some(SITE_FILES.into());
+        vec![route]
+    }
+}
@@ -0,0 +1,220 @@
+use git2::{Commit, ObjectType, Repository};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::auth;
+use crate::config::Credentials;
+use crate::deploy::DeployError;
+use crate::pull;
+
+/// a submodule as recorded in a commit
+struct Submodule {
+    name: String,
+    path: PathBuf,
+    url: String,
+    /// the commit the superproject points it at
+    commit: git2::Oid,
+}
+
+/// checks out every submodule `commit` records into `dir`, at the commits
+/// it records, and then theirs in turn. each submodule is fetched into a
+/// repository under the parent's `modules` directory the first time it's
+/// needed and again whenever the recorded commit isn't there yet, so this
+/// works the same for tags, previews and rollbacks as for the branch tip.
+/// submodules outside of `paths` are skipped when there are any
+pub fn export(
+    repo: &Repository,
+    commit: &Commit,
+    dir: &Path,
+    creds: &Credentials,
+    paths: &[String],
+) -> Result<(), DeployError> {
+    let error = |path: &Path| {
+        let path = path.display().to_string();
+        move |error| DeployError::Submodule { path, error }
+    };
+
+    for submodule in recorded(repo, commit).map_err(error(dir))? {
+        if !pull::checks_out(paths, &submodule.path) {
+            continue;
+        }
+        let target = dir.join(&submodule.path);
+        let sub_repo = open(repo, &submodule).map_err(error(&target))?;
+        let sub_commit = match sub_repo.find_commit(submodule.commit) {
+            Ok(x) => x,
+            Err(_) => {
+                info!(
+                    "fetching submodule {} from {}",
+                    submodule.name, submodule.url
+                );
+                let mut remote = sub_repo.find_remote("origin").map_err(error(&target))?;
+                pull::fetch_pruned(
+                    &[
+                        "+refs/heads/*:refs/remotes/origin/*",
+                        "+refs/tags/*:refs/tags/*",
+                    ],
+                    &mut remote,
+                    auth::callbacks(creds),
+                    None,
+                )
+                .map_err(error(&target))?;
+                sub_repo
+                    .find_commit(submodule.commit)
+                    .map_err(error(&target))?
+            }
+        };
+
+        fs::create_dir_all(&target).map_err(DeployError::Release)?;
+        sub_repo
+            .checkout_tree(
+                sub_commit.as_object(),
+                Some(
+                    git2::build::CheckoutBuilder::new()
+                        .target_dir(&target)
+                        .update_index(false)
+                        .force(),
+                ),
+            )
+            .map_err(error(&target))?;
+        export(&sub_repo, &sub_commit, &target, creds, &[])?;
+    }
+    Ok(())
+}
+
+/// the submodules listed in the commit's `.gitmodules` that it has a
+/// gitlink for
+fn recorded(repo: &Repository, commit: &Commit) -> Result<Vec<Submodule>, git2::Error> {
+    let tree = commit.tree()?;
+    let gitmodules = match tree.get_path(Path::new(".gitmodules")) {
+        Ok(entry) => entry.to_object(repo)?.peel_to_blob()?,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    // libgit2 only parses config from files
+    let parsed = std::env::temp_dir().join(format!(
+        "bloghoster-{}-{}.gitmodules",
+        std::process::id(),
+        gitmodules.id()
+    ));
+    fs::write(&parsed, gitmodules.content()).map_err(|e| git2::Error::from_str(&e.to_string()))?;
+    let config = git2::Config::open(&parsed).and_then(|mut x| x.snapshot());
+    let _ = fs::remove_file(&parsed);
+    let config = config?;
+
+    let base = repo
+        .find_remote("origin")
+        .ok()
+        .and_then(|x| x.url().map(|x| x.to_string()));
+    let mut submodules = Vec::new();
+    let mut entries = config.entries(Some(r"submodule\..*\.path"))?;
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        let (name, path) = match (entry.name(), entry.value()) {
+            (Some(name), Some(path)) => (name, path),
+            _ => continue,
+        };
+        let name = name
+            .trim_start_matches("submodule.")
+            .trim_end_matches(".path")
+            .to_string();
+        // the name picks the directory under `modules`, like git refuse
+        // anything that could point outside of it
+        if !is_safe(&name) || !is_safe(path) {
+            return Err(git2::Error::from_str(&format!(
+                "refusing submodule {} at {}, it would be written outside the repository",
+                name, path
+            )));
+        }
+        let url = config.get_string(&format!("submodule.{}.url", name))?;
+        let url = match &base {
+            Some(base) if url.starts_with("./") || url.starts_with("../") => {
+                resolve_url(base, &url)
+            }
+            _ => url,
+        };
+
+        // a path in .gitmodules that the tree doesn't have is just stale
+        let commit = match tree.get_path(Path::new(path)) {
+            Ok(x) if x.kind() == Some(ObjectType::Commit) => x.id(),
+            _ => continue,
+        };
+        submodules.push(Submodule {
+            name,
+            path: PathBuf::from(path),
+            url,
+            commit,
+        });
+    }
+    Ok(submodules)
+}
+
+/// whether a submodule name or path from `.gitmodules` stays where it's
+/// put, it can't be absolute or have `..` in it with either kind of slash
+fn is_safe(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with(['/', '\\']) && name.split(['/', '\\']).all(|x| x != "..")
+}
+
+/// the submodule's repository, created the first time round. it's never
+/// checked out but libgit2 won't export a tree with its own submodules
+/// from a bare repository
+fn open(parent: &Repository, submodule: &Submodule) -> Result<Repository, git2::Error> {
+    let path = parent.path().join("modules").join(&submodule.name);
+    let repo = match Repository::open(&path) {
+        Ok(x) => x,
+        Err(_) => Repository::init(&path)?,
+    };
+    let url = repo
+        .find_remote("origin")
+        .ok()
+        .and_then(|x| x.url().map(|x| x.to_string()));
+    match url {
+        Some(url) if url == submodule.url => {}
+        Some(_) => repo.remote_set_url("origin", &submodule.url)?,
+        None => {
+            repo.remote("origin", &submodule.url)?;
+        }
+    }
+    Ok(repo)
+}
+
+/// resolves a url like `../theme.git` against the parent's remote, the way
+/// git does for relative submodule urls
+fn resolve_url(base: &str, relative: &str) -> String {
+    let mut base = base.trim_end_matches('/').to_string();
+    let mut relative = relative;
+    loop {
+        if let Some(rest) = relative.strip_prefix("./") {
+            relative = rest;
+        } else if let Some(rest) = relative.strip_prefix("../") {
+            relative = rest;
+            // scp-like remotes separate the path with a `:`
+            match base.rfind(['/', ':']) {
+                Some(i) if base[i..].starts_with(':') => base.truncate(i + 1),
+                Some(i) => base.truncate(i),
+                None => base.clear(),
+            }
+        } else {
+            break;
+        }
+    }
+    let separator = if base.ends_with(':') { "" } else { "/" };
+    format!("{}{}{}", base, separator, relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_that_leave_the_modules_directory_are_unsafe() {
+        assert!(is_safe("theme"));
+        assert!(is_safe("themes/dark"));
+        assert!(is_safe("..theme"));
+        assert!(!is_safe(""));
+        assert!(!is_safe("../../../x"));
+        assert!(!is_safe("themes/../../x"));
+        assert!(!is_safe("themes\\..\\..\\x"));
+        assert!(!is_safe("/etc/x"));
+        assert!(!is_safe("\\x"));
+    }
+}
@@ -0,0 +1,195 @@
+use git2::Repository;
+use std::cmp::Ordering;
+
+use crate::glob;
+
+/// a tag name read as a version, `v1.2.3-rc.1` and `release-1.2` both work.
+/// missing parts count as zero
+#[derive(Debug, PartialEq, Eq)]
+struct Version {
+    numbers: [u64; 3],
+    /// the dot separated identifiers after the `-`, empty for a release
+    pre_release: Vec<Identifier>,
+}
+
+/// one part of a pre-release, numeric ones are compared as numbers and
+/// come before the rest
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Identifier {
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+impl Version {
+    fn parse(tag: &str) -> Option<Self> {
+        let start = tag.find(|c: char| c.is_ascii_digit())?;
+        let tag = &tag[start..];
+        // build metadata doesn't take part in ordering, and can have a `-`
+        // of its own
+        let tag = tag.split('+').next().unwrap_or(tag);
+        let (core, pre_release) = match tag.split_once('-') {
+            Some((core, pre)) => (core, pre.split('.').map(Identifier::parse).collect()),
+            None => (tag, Vec::new()),
+        };
+
+        let mut numbers = [0; 3];
+        let mut parts = core.split('.');
+        for number in numbers.iter_mut() {
+            match parts.next() {
+                Some(x) => *number = x.parse().ok()?,
+                None => break,
+            }
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            numbers,
+            pre_release,
+        })
+    }
+}
+
+impl Identifier {
+    fn parse(part: &str) -> Self {
+        match part.parse() {
+            Ok(x) if part.bytes().all(|c| c.is_ascii_digit()) => Identifier::Numeric(x),
+            _ => Identifier::Alphanumeric(part.to_string()),
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // a pre-release comes before the release it leads up to, otherwise
+        // they're compared part by part and a longer one wins a tie
+        let pre = match (self.pre_release.is_empty(), other.pre_release.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => self.pre_release.cmp(&other.pre_release),
+        };
+        self.numbers.cmp(&other.numbers).then(pre)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// the newest tag matching `pattern` and the commit it points at. tags
+/// that read as versions are ordered by version and come before those that
+/// don't, which are ordered by commit time
+pub fn newest<'r>(
+    repo: &'r Repository,
+    pattern: &str,
+) -> Result<Option<(String, git2::Commit<'r>)>, git2::Error> {
+    let mut newest: Option<((Option<Version>, i64), String, git2::Commit)> = None;
+    for name in repo.tag_names(None)?.iter().flatten() {
+        if !glob::matches(pattern, name) {
+            continue;
+        }
+        let commit = repo
+            .revparse_single(&format!("refs/tags/{}", name))
+            .and_then(|x| x.peel_to_commit());
+        // tags can point at trees and blobs too, those can't be deployed
+        let commit = match commit {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let key = (Version::parse(name), commit.time().seconds());
+        if newest.as_ref().is_none_or(|x| key > x.0) {
+            newest = Some((key, name.to_string(), commit));
+        }
+    }
+    Ok(newest.map(|(_, name, commit)| (name, commit)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(tag: &str) -> Version {
+        Version::parse(tag).unwrap()
+    }
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(version("v1.2.3").numbers, [1, 2, 3]);
+        assert_eq!(version("release-1.2").numbers, [1, 2, 0]);
+        assert!(version("v1.2.3").pre_release.is_empty());
+        assert_eq!(
+            version("v1.2.3-rc.10").pre_release,
+            [
+                Identifier::Alphanumeric("rc".to_string()),
+                Identifier::Numeric(10)
+            ]
+        );
+        assert!(Version::parse("v1.2.3.4").is_none());
+        assert!(Version::parse("latest").is_none());
+    }
+
+    #[test]
+    fn build_metadata_is_ignored() {
+        assert_eq!(version("v1.2.3+build-1"), version("v1.2.3"));
+        assert_eq!(version("v1.2.3-rc.1+build-2"), version("v1.2.3-rc.1"));
+    }
+
+    #[test]
+    fn orders_like_semver() {
+        let ordered = [
+            "v1.0.0-alpha",
+            "v1.0.0-alpha.1",
+            "v1.0.0-alpha.beta",
+            "v1.0.0-beta",
+            "v1.0.0-beta.2",
+            "v1.0.0-beta.11",
+            "v1.0.0-rc.1",
+            "v1.0.0",
+            "v1.2.3-rc.2",
+            "v1.2.3-rc.10",
+            "v1.2.3",
+            "v1.10.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(
+                version(pair[0]) < version(pair[1]),
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn newest_picks_the_highest_matching_version() {
+        let dir = std::env::temp_dir().join(format!("bloghoster-tags-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = Repository::init(&dir).unwrap();
+        let signature = git2::Signature::now("t", "t@t").unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let commit = repo
+            .commit(None, &signature, &signature, "c", &tree, &[])
+            .unwrap();
+        let commit = repo.find_object(commit, None).unwrap();
+        for tag in [
+            "v1.2.3-rc.2",
+            "v1.2.3-rc.10",
+            "v1.2.2+build-9",
+            "other-9.0.0",
+        ] {
+            repo.tag_lightweight(tag, &commit, false).unwrap();
+        }
+
+        let found = newest(&repo, "v*").unwrap().map(|x| x.0);
+        assert_eq!(found.as_deref(), Some("v1.2.3-rc.10"));
+        repo.tag_lightweight("v1.2.3", &commit, false).unwrap();
+        let found = newest(&repo, "v*").unwrap().map(|x| x.0);
+        assert_eq!(found.as_deref(), Some("v1.2.3"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
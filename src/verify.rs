@@ -0,0 +1,129 @@
+use git2::{Oid, Repository};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::config::{SignatureConfig, SiteConfig};
+use crate::deploy::DeployError;
+
+/// refuses `commit` unless it's signed by one of the site's trusted keys,
+/// when the site asks for signatures at all
+pub fn check(site: &SiteConfig, repo: &Repository, commit: Oid) -> Result<(), DeployError> {
+    let trusted = match &site.signatures {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    match signer(repo, commit, trusted) {
+        Ok(signer) => {
+            info!("{} is signed by {}", commit, signer);
+            Ok(())
+        }
+        Err(reason) => Err(DeployError::Signature(format!("{}: {}", commit, reason))),
+    }
+}
+
+/// who signed `commit`, checked the same way git does by handing the
+/// signature to ssh-keygen or gpgv
+fn signer(repo: &Repository, commit: Oid, trusted: &SignatureConfig) -> Result<String, String> {
+    let (signature, payload) = match repo.extract_signature(&commit, None) {
+        Ok(x) => x,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Err("not signed".to_string()),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let sig_file =
+        std::env::temp_dir().join(format!("bloghoster-{}-{}.sig", std::process::id(), commit));
+    fs::write(&sig_file, &*signature).map_err(|e| e.to_string())?;
+
+    let result = if signature.starts_with(b"-----BEGIN SSH SIGNATURE-----") {
+        match &trusted.allowed_signers {
+            Some(allowed) => ssh_signer(allowed, &sig_file, &payload),
+            None => Err("signed with ssh but no allowed_signers are configured".to_string()),
+        }
+    } else if signature.starts_with(b"-----BEGIN PGP SIGNATURE-----") {
+        match &trusted.gpg_keyring {
+            Some(keyring) => gpg_signer(keyring, &sig_file, &payload),
+            None => Err("signed with gpg but no gpg_keyring is configured".to_string()),
+        }
+    } else {
+        Err("signed in a format that isn't supported".to_string())
+    };
+
+    let _ = fs::remove_file(&sig_file);
+    result
+}
+
+fn ssh_signer(allowed: &Path, sig_file: &Path, payload: &[u8]) -> Result<String, String> {
+    let (found, principals) = run(
+        Command::new("ssh-keygen")
+            .args(["-Y", "find-principals", "-f"])
+            .arg(allowed)
+            .arg("-s")
+            .arg(sig_file),
+        &[],
+    )?;
+    let principal = match principals.lines().next() {
+        Some(x) if found => x.trim().to_string(),
+        _ => return Err("not signed by an allowed signer".to_string()),
+    };
+
+    let (verified, output) = run(
+        Command::new("ssh-keygen")
+            .args(["-Y", "verify", "-n", "git", "-f"])
+            .arg(allowed)
+            .arg("-I")
+            .arg(&principal)
+            .arg("-s")
+            .arg(sig_file),
+        payload,
+    )?;
+    if !verified {
+        return Err(format!("bad ssh signature: {}", output.trim()));
+    }
+    Ok(principal)
+}
+
+fn gpg_signer(keyring: &Path, sig_file: &Path, payload: &[u8]) -> Result<String, String> {
+    // gpgv looks relative paths up in its home directory
+    let keyring = fs::canonicalize(keyring).map_err(|e| format!("gpg_keyring: {}", e))?;
+    let (verified, output) = run(
+        Command::new("gpgv")
+            .arg("--keyring")
+            .arg(keyring)
+            .args(["--status-fd", "1"])
+            .arg(sig_file)
+            .arg("-"),
+        payload,
+    )?;
+
+    let good = output
+        .lines()
+        .find_map(|x| x.strip_prefix("[GNUPG:] GOODSIG "));
+    match good {
+        Some(signer) if verified => Ok(signer.to_string()),
+        _ => Err("not signed by a trusted gpg key".to_string()),
+    }
+}
+
+/// runs `command` with `input` on stdin, returning whether it succeeded
+/// along with everything it printed
+fn run(command: &mut Command, input: &[u8]) -> Result<(bool, String), String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run {:?}: {}", command.get_program(), e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // a verifier that gave up early closes its end, which isn't our error
+        let _ = stdin.write_all(input);
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    let printed = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok((output.status.success(), printed))
+}
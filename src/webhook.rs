@@ -0,0 +1,147 @@
+use rocket::data::{self, Data, FromData, ToByteUnit};
+use rocket::http::Status;
+use rocket::serde::Deserialize;
+use rocket::Request;
+
+use crate::signing;
+use crate::sites::Sites;
+
+/// the git host that sent the webhook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    GitHub,
+    Gitea,
+    GitLab,
+}
+
+#[derive(Debug)]
+pub enum WebhookError {
+    /// no secret is configured so nothing can be verified
+    NoSecret,
+    /// none of the known signature headers were present
+    Unsigned,
+    BadSignature,
+    Io(std::io::Error),
+    TooLarge,
+    Payload(serde_json::Error),
+}
+
+impl std::fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebhookError::NoSecret => write!(f, "no webhook secret is configured"),
+            WebhookError::Unsigned => write!(f, "request carried no signature"),
+            WebhookError::BadSignature => write!(f, "signature did not match"),
+            WebhookError::Io(e) => write!(f, "failed to read body: {}", e),
+            WebhookError::TooLarge => write!(f, "payload too large"),
+            WebhookError::Payload(e) => write!(f, "invalid push payload: {}", e),
+        }
+    }
+}
+
+fn reject<'r>(status: Status, error: WebhookError) -> data::Outcome<'r, Push> {
+    warn!("rejected webhook: {}", error);
+    data::Outcome::Error((status, error))
+}
+
+/// the fields of a push event we care about, github, gitea and gitlab
+/// all use the same name for the updated ref
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct PushPayload {
+    #[serde(rename = "ref")]
+    git_ref: Option<String>,
+}
+
+/// a push webhook whose signature has been verified against the
+/// configured secret
+#[derive(Debug)]
+pub struct Push {
+    pub provider: Provider,
+    pub git_ref: Option<String>,
+}
+
+impl Push {
+    /// the branch that was pushed to, `None` for tags and ping events
+    pub fn branch(&self) -> Option<&str> {
+        self.git_ref.as_deref()?.strip_prefix("refs/heads/")
+    }
+
+    /// the tag that was pushed, `None` for branches and ping events
+    pub fn tag(&self) -> Option<&str> {
+        self.git_ref.as_deref()?.strip_prefix("refs/tags/")
+    }
+}
+
+/// compares without bailing on the first mismatched byte so the token
+/// can't be guessed from response times
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn verify(req: &Request<'_>, secret: &str, body: &[u8]) -> Result<Provider, WebhookError> {
+    let headers = req.headers();
+
+    if let Some(signature) = headers.get_one("X-Hub-Signature-256") {
+        let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+        return match signing::verify(secret, body, signature) {
+            true => Ok(Provider::GitHub),
+            false => Err(WebhookError::BadSignature),
+        };
+    }
+    if let Some(signature) = headers.get_one("X-Gitea-Signature") {
+        return match signing::verify(secret, body, signature) {
+            true => Ok(Provider::Gitea),
+            false => Err(WebhookError::BadSignature),
+        };
+    }
+    if let Some(token) = headers.get_one("X-Gitlab-Token") {
+        return match constant_time_eq(token.as_bytes(), secret.as_bytes()) {
+            true => Ok(Provider::GitLab),
+            false => Err(WebhookError::BadSignature),
+        };
+    }
+    Err(WebhookError::Unsigned)
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for Push {
+    type Error = WebhookError;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let secret = match req
+            .rocket()
+            .state::<Sites>()
+            .and_then(|x| x.for_request(req))
+            .and_then(|x| x.config.webhook_secret.as_deref())
+        {
+            Some(x) => x,
+            None => return reject(Status::Unauthorized, WebhookError::NoSecret),
+        };
+
+        let limit = req.limits().get("webhook").unwrap_or(5.mebibytes());
+        let body = match data.open(limit).into_bytes().await {
+            Ok(x) if x.is_complete() => x.into_inner(),
+            Ok(_) => return reject(Status::PayloadTooLarge, WebhookError::TooLarge),
+            Err(e) => return reject(Status::InternalServerError, WebhookError::Io(e)),
+        };
+
+        let provider = match verify(req, secret, &body) {
+            Ok(x) => x,
+            Err(e) => return reject(Status::Unauthorized, e),
+        };
+
+        let payload: PushPayload = match serde_json::from_slice(&body) {
+            Ok(x) => x,
+            Err(e) => return reject(Status::BadRequest, WebhookError::Payload(e)),
+        };
+
+        data::Outcome::Success(Push {
+            provider,
+            git_ref: payload.git_ref,
+        })
+    }
+}